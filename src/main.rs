@@ -27,32 +27,182 @@ use std::cmp;
 use std::collections::HashMap;
 //Buttplug Lib
 use buttplug::{
-    client::{device::ScalarValueCommand, ButtplugClient, ButtplugClientError},
+    client::{device::{LinearCommand, RotateCommand, ScalarValueCommand}, ButtplugClient, ButtplugClientDevice, ButtplugClientError},
     core::{
-        connector::new_json_ws_client_connector, message::ClientGenericDeviceMessageAttributes,
+        connector::{new_json_ws_client_connector, ButtplugInProcessClientConnectorBuilder},
+        errors::ButtplugDeviceError,
+        message::{ClientGenericDeviceMessageAttributes, Endpoint},
+        ButtplugResultFuture,
+    },
+    server::{
+        device::{
+            configuration::{BluetoothLESpecifier, ProtocolCommunicationSpecifier},
+            hardware::{
+                communication::{HardwareCommunicationManager, HardwareCommunicationManagerBuilder, HardwareCommunicationManagerEvent},
+                Hardware, HardwareConnector, HardwareEvent, HardwareInternal, HardwareReadCmd, HardwareReading,
+                HardwareSpecializer, HardwareSubscribeCmd, HardwareUnsubscribeCmd, HardwareWriteCmd,
+            },
+        },
+        ButtplugServerBuilder,
     },
 };
-// Set extremely long so that power events cancelling eachother out don't run out midgame. Bug occurs if game lasts longer than 1 day.
-const EVENT_POWER_DURATION:Duration = Duration::from_secs(86400);
+use futures::future::{BoxFuture, FutureExt};
+// BAR runs its simulation at 30 frames per second at normal game speed
+const GAME_FRAMES_PER_SECOND: f64 = 30.0;
+// Minimum wall time between two game speed measurements, shorter gaps are too noisy
+const GAME_SPEED_SAMPLE_WINDOW: Duration = Duration::from_secs(2);
+// How much each new measurement moves the game speed estimate
+const GAME_SPEED_SMOOTHING: f64 = 0.3;
+// BAR speeds range from 0.1x to 20x
+const GAME_SPEED_MIN: f64 = 0.1;
+const GAME_SPEED_MAX: f64 = 20.0;
+// Fixed step the simulator advances by, so results don't depend on how often the GUI repaints
+const SIM_TIME_STEP: Duration = Duration::from_millis(10);
+// Most time one tick will catch up on, anything beyond it (window minimised, machine asleep) is dropped
+const SIM_MAX_CATCH_UP: Duration = Duration::from_secs(5);
 
 // use buttplug::core::connector::ButtplugConnectorError;
-#[derive(Debug)]
-enum BPActionType {
+#[derive(Debug, Clone)]
+pub enum BPActionType {
     Stop,
     Vibrate { strength: f64, motor: i8 },
     Power { strength: f64, motor: i8 },
+    //Keyed persistent event, updated in place by later SETs with the same key
+    Set { key: String, strength: f64, motor: i8 },
+    Clear { key: String },
+    Cancel { filter: BPCancelFilter },
+    Pause,
+    Resume,
+    //Changes the mixing policy of one motor, one tag, or the default when neither is given
+    Mix { policy: BPMixPolicy, motor: Option<i8>, tag: Option<String> },
+    //Emergency stop, handled by the app before it reaches the simulator
+    Panic,
     Stroke,
 }
+
+impl BPActionType {
+    //Name of the command that creates this kind of action
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            BPActionType::Stop => "RESET",
+            BPActionType::Vibrate { .. } => "VIBRATE",
+            BPActionType::Power { .. } => "POWER",
+            BPActionType::Set { .. } => "SET",
+            BPActionType::Clear { .. } => "CLEAR",
+            BPActionType::Cancel { .. } => "CANCEL",
+            BPActionType::Pause => "PAUSE",
+            BPActionType::Resume => "RESUME",
+            BPActionType::Mix { .. } => "MIX",
+            BPActionType::Panic => "PANIC",
+            BPActionType::Stroke => "STROKE",
+        }
+    }
+}
+
+//Selects which events a CANCEL command finishes
+#[derive(Debug, Clone)]
+pub enum BPCancelFilter {
+    Id(String),
+    Tag(String),
+    Type(String),
+    //Only events carrying the tag that also match the filter, so one source can't cancel another's events
+    Scoped { tag: String, filter: Box<BPCancelFilter> },
+}
+
+//How the strengths of events sharing a motor are combined into its floor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BPMixPolicy {
+    //Strengths add up, capped at full power
+    AdditiveSaturating,
+    //The strongest event wins
+    Max,
+    //Square root of the sum of squares, capped at full power
+    RootSumSquare,
+    //Strengths add up, but events below the highest priority are scaled down by the duck factor
+    PriorityDucking,
+}
+
+impl BPMixPolicy {
+    pub const ALL: [BPMixPolicy; 4] = [
+        BPMixPolicy::AdditiveSaturating,
+        BPMixPolicy::Max,
+        BPMixPolicy::RootSumSquare,
+        BPMixPolicy::PriorityDucking,
+    ];
+
+    //Parses the Policy argument of a MIX command
+    pub fn from_name(name: &str) -> Option<BPMixPolicy> {
+        match name.to_uppercase().as_str() {
+            "ADD" | "ADDITIVE" => Some(BPMixPolicy::AdditiveSaturating),
+            "MAX" => Some(BPMixPolicy::Max),
+            "RSS" | "ROOTSUMSQUARE" => Some(BPMixPolicy::RootSumSquare),
+            "DUCK" | "PRIORITY" => Some(BPMixPolicy::PriorityDucking),
+            _ => None,
+        }
+    }
+
+    //Combines (strength, priority) pairs into a single strength between 0 and 1
+    pub fn mix(&self, contributions: &[(f64, i32)], duck_factor: f64) -> f64 {
+        let mixed = match self {
+            BPMixPolicy::AdditiveSaturating => contributions.iter().map(|(strength, _)| strength).sum(),
+            BPMixPolicy::Max => contributions.iter().fold(0.0, |loudest: f64, (strength, _)| loudest.max(*strength)),
+            BPMixPolicy::RootSumSquare => contributions
+                .iter()
+                .map(|(strength, _)| strength.max(0.0).powi(2))
+                .sum::<f64>()
+                .sqrt(),
+            BPMixPolicy::PriorityDucking => {
+                let top_priority = contributions.iter().map(|(_, priority)| *priority).max().unwrap_or(0);
+                contributions
+                    .iter()
+                    .map(|(strength, priority)| if *priority == top_priority { *strength } else { strength * duck_factor })
+                    .sum()
+            }
+        };
+        mixed.clamp(0.0, 1.0)
+    }
+
+    //Drop down for picking a policy, None is offered as "Default" when allowed. Returns true if the selection changed.
+    pub fn show_combo(ui: &mut egui::Ui, id_source: String, selected: &mut Option<BPMixPolicy>, allow_default: bool) -> bool {
+        let mut changed = false;
+        egui::ComboBox::from_id_source(id_source)
+            .selected_text(match selected {
+                None => "Default".to_string(),
+                Some(policy) => format!("{:?}", policy),
+            })
+            .show_ui(ui, |ui| {
+                if allow_default {
+                    changed |= ui.selectable_value(selected, None, "Default").changed();
+                }
+                for policy in BPMixPolicy::ALL {
+                    changed |= ui.selectable_value(selected, Some(policy), format!("{:?}", policy)).changed();
+                }
+            });
+        changed
+    }
+}
 #[derive(Debug)]
 enum BPEffectorType {
     Vibrates { intensity: f64 },
     Strokes { amplitude: f64 },
 }
-#[derive(Debug)]
-struct BPSimEvent {
+#[derive(Debug, Clone)]
+pub struct BPSimEvent {
     pub finished: bool,
     time_remaining: Duration,
+    //Persistent events never run out of time, they must be finished explicitly
+    persistent: bool,
     action: BPActionType,
+    //Time left before the event starts, counted down in the simulator's pending queue
+    start_delay: Duration,
+    //Optional handles used by CANCEL to finish only some events
+    id: Option<String>,
+    tags: Vec<String>,
+    //Number of additional times the event starts again, each repeat_interval after the previous start
+    repeats_remaining: u32,
+    repeat_interval: Duration,
+    //Used by the priority ducking mix policy, higher priorities duck lower ones
+    priority: i32,
 }
 
 impl BPSimEvent {
@@ -60,18 +210,46 @@ impl BPSimEvent {
         BPSimEvent {
             finished: false,
             time_remaining: initial_duration,
+            persistent: false,
             action: action,
+            start_delay: Duration::ZERO,
+            id: None,
+            tags: Vec::new(),
+            repeats_remaining: 0,
+            repeat_interval: Duration::ZERO,
+            priority: 0,
+        }
+    }
+    pub fn new_persistent(action: BPActionType) -> BPSimEvent {
+        BPSimEvent {
+            finished: false,
+            time_remaining: Duration::ZERO,
+            persistent: true,
+            action,
+            start_delay: Duration::ZERO,
+            id: None,
+            tags: Vec::new(),
+            repeats_remaining: 0,
+            repeat_interval: Duration::ZERO,
+            priority: 0,
         }
     }
     pub fn new_stop_event() -> BPSimEvent {
         BPSimEvent {
             finished: true,
             time_remaining: Duration::ZERO,
+            persistent: false,
             action: BPActionType::Stop,
+            start_delay: Duration::ZERO,
+            id: None,
+            tags: Vec::new(),
+            repeats_remaining: 0,
+            repeat_interval: Duration::ZERO,
+            priority: 0,
         }
     }
     pub fn pass_time(&mut self, time_passed: Duration) {
-        if (self.finished) {
+        if (self.finished || self.persistent) {
             return;
         }
         self.time_remaining = match self.time_remaining.checked_sub(time_passed) {
@@ -94,11 +272,242 @@ impl BPSimEvent {
     {
         self.finished = true;
     }
+    pub fn matches_filter(&self, filter: &BPCancelFilter) -> bool {
+        match filter {
+            BPCancelFilter::Id(id) => self.id.as_ref() == Some(id),
+            BPCancelFilter::Tag(tag) => self.tags.contains(tag),
+            BPCancelFilter::Type(type_name) => self.action.type_name() == type_name,
+            BPCancelFilter::Scoped { tag, filter } => self.tags.contains(tag) && self.matches_filter(filter),
+        }
+    }
+    pub fn scale_strength(&mut self, factor: f64) {
+        match self.action {
+            BPActionType::Vibrate { ref mut strength, .. }
+            | BPActionType::Power { ref mut strength, .. }
+            | BPActionType::Set { ref mut strength, .. } => {
+                *strength *= factor;
+            }
+            _ => {}
+        }
+    }
+    //Strength and motor of events that drive a vibrator
+    pub fn vibration(&self) -> Option<(f64, i8)> {
+        match self.action {
+            BPActionType::Vibrate { strength, motor }
+            | BPActionType::Power { strength, motor }
+            | BPActionType::Set { strength, motor, .. } => Some((strength, motor)),
+            _ => None,
+        }
+    }
+    //Events that can make a device move, as opposed to ones that stop, lower or configure things
+    pub fn starts_output(&self) -> bool {
+        match self.action {
+            BPActionType::Vibrate { strength, .. }
+            | BPActionType::Power { strength, .. }
+            | BPActionType::Set { strength, .. } => strength > 0.0,
+            BPActionType::Stroke => true,
+            _ => false,
+        }
+    }
+    //Short lived events that are safe to drop or shorten when read late
+    pub fn is_transient(&self) -> bool {
+        !self.persistent && matches!(self.action, BPActionType::Vibrate { .. } | BPActionType::Stroke)
+    }
+    //Skips the event ahead as if it had started earlier. Returns false if it would already be over.
+    pub fn skip_time(&mut self, time_skipped: Duration) -> bool {
+        let delay_skipped = Duration::min(self.start_delay, time_skipped);
+        self.start_delay -= delay_skipped;
+        //Anything else only ends when told to, so just the delay is skipped
+        if !self.is_transient() {
+            return true;
+        }
+        let active_time_skipped = time_skipped - delay_skipped;
+        if active_time_skipped >= self.time_remaining && active_time_skipped > Duration::ZERO {
+            return false;
+        }
+        self.time_remaining -= active_time_skipped;
+        true
+    }
+    //Counts down the start delay, returns true once the event is ready to start
+    pub fn pass_delay(&mut self, time_passed: Duration) -> bool {
+        self.start_delay = self.start_delay.saturating_sub(time_passed);
+        self.start_delay == Duration::ZERO
+    }
+}
+
+//How an effector's intensity falls off once events stop holding it up
+pub trait BPDecayModel: std::fmt::Debug {
+    //Name shown in the GUI, also accepted by new_decay_model
+    fn name(&self) -> &'static str;
+    //Intensity left after time_passed, starting from current_intensity
+    fn decay(&self, time_passed: Duration, current_intensity: f64) -> f64;
+    //Controls for the model's parameters
+    fn show_ui(&mut self, ui: &mut egui::Ui);
+}
+
+pub const BP_DECAY_MODEL_NAMES: [&str; 4] = ["Exponential", "Linear", "None", "Curve"];
+
+//Builds a decay model with default parameters from its name
+pub fn new_decay_model(name: &str) -> Option<Box<dyn BPDecayModel>> {
+    match name {
+        "Exponential" => Some(Box::<BPExponentialDecay>::default()),
+        "Linear" => Some(Box::<BPLinearDecay>::default()),
+        "None" => Some(Box::new(BPNoDecay)),
+        "Curve" => Some(Box::<BPCurveDecay>::default()),
+        _ => None,
+    }
+}
+
+//Half life decay with a small linear offset, the original vibrator model
+#[derive(Debug)]
+pub struct BPExponentialDecay {
+    pub half_life: Duration,
+    pub linear_reduction: f64,
+}
+
+impl Default for BPExponentialDecay {
+    fn default() -> BPExponentialDecay {
+        BPExponentialDecay {
+            half_life: Duration::from_millis(200),
+            linear_reduction: 0.005,
+        }
+    }
+}
+
+impl BPDecayModel for BPExponentialDecay {
+    fn name(&self) -> &'static str {
+        "Exponential"
+    }
+    fn decay(&self, time_passed: Duration, current_intensity: f64) -> f64 {
+        BPSimulator::calc_intensity_decay(time_passed, current_intensity, self.linear_reduction, self.half_life)
+    }
+    fn show_ui(&mut self, ui: &mut egui::Ui) {
+        let mut half_life_millis = self.half_life.as_millis() as u64;
+        if ui.add(egui::Slider::new(&mut half_life_millis, 10..=5000).text("Half life (millis)")).changed() {
+            self.half_life = Duration::from_millis(half_life_millis);
+        }
+        ui.add(egui::Slider::new(&mut self.linear_reduction, 0.0..=0.1).text("Linear reduction"));
+    }
+}
+
+//Drops by a fixed amount per second
+#[derive(Debug)]
+pub struct BPLinearDecay {
+    pub per_second: f64,
+}
+
+impl Default for BPLinearDecay {
+    fn default() -> BPLinearDecay {
+        BPLinearDecay { per_second: 2.0 }
+    }
+}
+
+impl BPDecayModel for BPLinearDecay {
+    fn name(&self) -> &'static str {
+        "Linear"
+    }
+    fn decay(&self, time_passed: Duration, current_intensity: f64) -> f64 {
+        f64::max(current_intensity - self.per_second * time_passed.as_secs_f64(), 0.0)
+    }
+    fn show_ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::Slider::new(&mut self.per_second, 0.1..=20.0).text("Reduction per second"));
+    }
+}
+
+//No tail at all, the effector follows its active events exactly
+#[derive(Debug)]
+pub struct BPNoDecay;
+
+impl BPDecayModel for BPNoDecay {
+    fn name(&self) -> &'static str {
+        "None"
+    }
+    fn decay(&self, _time_passed: Duration, _current_intensity: f64) -> f64 {
+        0.0
+    }
+    fn show_ui(&mut self, _ui: &mut egui::Ui) {}
+}
+
+//User defined table of (intensity, reduction per second) points, linearly interpolated between points.
+//Written as intensity:rate pairs separated by spaces, e.g. "0:0.5 0.5:1 1:4" for a fast drop from high intensities.
+#[derive(Debug)]
+pub struct BPCurveDecay {
+    points: Vec<(f64, f64)>,
+    points_text: String,
+}
+
+impl Default for BPCurveDecay {
+    fn default() -> BPCurveDecay {
+        BPCurveDecay::parse("0:0.5 0.5:1 1:4").unwrap()
+    }
+}
+
+impl BPCurveDecay {
+    //Longest step the curve is followed in one go, keeps long ticks from overshooting
+    const STEP: Duration = Duration::from_millis(10);
+
+    pub fn parse(points_text: &str) -> Option<BPCurveDecay> {
+        let mut points: Vec<(f64, f64)> = Vec::new();
+        for point in points_text.split_whitespace() {
+            let (intensity, rate) = point.split_once(':')?;
+            points.push((intensity.parse().ok()?, rate.parse().ok()?));
+        }
+        if points.is_empty() {
+            return None;
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Some(BPCurveDecay {
+            points,
+            points_text: points_text.to_string(),
+        })
+    }
+
+    //Reduction per second at this intensity, flat beyond the first and last points
+    pub fn rate_at(&self, intensity: f64) -> f64 {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if intensity <= first.0 {
+            return first.1;
+        }
+        if intensity >= last.0 {
+            return last.1;
+        }
+        let upper_index = self.points.iter().position(|point| point.0 >= intensity).unwrap();
+        let (low, high) = (self.points[upper_index - 1], self.points[upper_index]);
+        low.1 + (high.1 - low.1) * (intensity - low.0) / (high.0 - low.0)
+    }
+}
+
+impl BPDecayModel for BPCurveDecay {
+    fn name(&self) -> &'static str {
+        "Curve"
+    }
+    fn decay(&self, time_passed: Duration, current_intensity: f64) -> f64 {
+        let mut intensity = current_intensity;
+        let mut time_left = time_passed;
+        while time_left > Duration::ZERO && intensity > 0.0 {
+            let step = Duration::min(time_left, BPCurveDecay::STEP);
+            intensity = f64::max(intensity - self.rate_at(intensity) * step.as_secs_f64(), 0.0);
+            time_left -= step;
+        }
+        intensity
+    }
+    fn show_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Intensity:rate points");
+        if ui.text_edit_singleline(&mut self.points_text).changed() {
+            //Keep the old points until the text parses again
+            if let Some(curve) = BPCurveDecay::parse(&self.points_text) {
+                self.points = curve.points;
+            }
+        }
+    }
 }
+
 #[derive(Debug)]
 struct BPEffector {
     effector_type: BPEffectorType,
     index: i8,
+    decay_model: Box<dyn BPDecayModel>,
 }
 
 impl BPEffector
@@ -108,32 +517,97 @@ impl BPEffector
     BPEffector
     {
       effector_type,
-      index
+      index,
+      decay_model: Box::<BPExponentialDecay>::default(),
     }
   }
 }
 
+//Source of the current time for the simulator, swapped out in tests to control time
+pub trait BPClock: std::fmt::Debug {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug)]
+pub struct BPSystemClock;
+
+impl BPClock for BPSystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+//Only moves when advanced. Clones share the same time, so a test can keep one and hand another to the simulator.
+#[derive(Debug, Clone)]
+pub struct BPManualClock {
+    now: std::sync::Arc<std::sync::Mutex<Instant>>,
+}
+
+impl Default for BPManualClock {
+    fn default() -> BPManualClock {
+        BPManualClock {
+            now: std::sync::Arc::new(std::sync::Mutex::new(Instant::now())),
+        }
+    }
+}
+
+impl BPManualClock {
+    pub fn advance(&self, time_passed: Duration) {
+        *self.now.lock().unwrap() += time_passed;
+    }
+}
+
+impl BPClock for BPManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
 #[derive(Debug)]
 struct BPSimulator {
     events: Vec<BPSimEvent>,
+    //Events waiting for their start delay to run out
+    pending_events: Vec<BPSimEvent>,
     effectors: Vec<BPEffector>,
+    clock: Box<dyn BPClock>,
     last_sim_instant: Instant,
+    //Time passed that hasn't made up a whole step yet
+    unstepped_time: Duration,
+    time_step: Duration,
     formula_threshold: f64,
-    formula_half_life_vib: Duration,
-    formula_linear_reduction_vib: f64,
-    formula_floor_cache: HashMap<i8, f64>,
+    //Decay models of the last device's vibrators, handed to the next device's vibrators with the same index
+    saved_decay_models: HashMap<i8, Box<dyn BPDecayModel>>,
+    //Mixing policy for motors without their own
+    pub mix_policy: BPMixPolicy,
+    motor_mix_policies: HashMap<i8, BPMixPolicy>,
+    //Events carrying one of these tags are mixed together first, then mixed into the motor as one
+    tag_mix_policies: HashMap<String, BPMixPolicy>,
+    //How much lower priority events are scaled by under priority ducking
+    pub mix_duck_factor: f64,
+    //While paused event timers are frozen
+    paused: bool,
+    //Report all vibrators as stopped while paused
+    silence_when_paused: bool,
 }
 
 impl Default for BPSimulator {
     fn default() -> BPSimulator {
         BPSimulator {
             events: Vec::new(),
+            pending_events: Vec::new(),
             effectors: Vec::new(),
+            clock: Box::new(BPSystemClock),
             last_sim_instant: std::time::Instant::now(),
+            unstepped_time: Duration::ZERO,
+            time_step: SIM_TIME_STEP,
             formula_threshold: 0.01 as f64,
-            formula_half_life_vib: Duration::from_millis(200),
-            formula_linear_reduction_vib: 0.005 as f64,
-            formula_floor_cache: HashMap::new(),
+            saved_decay_models: HashMap::new(),
+            mix_policy: BPMixPolicy::AdditiveSaturating,
+            motor_mix_policies: HashMap::new(),
+            tag_mix_policies: HashMap::new(),
+            mix_duck_factor: 0.3,
+            paused: false,
+            silence_when_paused: true,
         }
     }
 }
@@ -142,25 +616,76 @@ impl BPSimulator {
     pub fn new() -> BPSimulator {
         Default::default()
     }
+    pub fn with_clock(clock: Box<dyn BPClock>) -> BPSimulator {
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.last_sim_instant = clock.now();
+        bp_sim.clock = clock;
+        bp_sim
+    }
+    //Advances the simulation up to the clock's current time
+    pub fn tick(&mut self) {
+        self.process_tick(self.clock.now());
+    }
     pub fn add_event(&mut self, event: BPSimEvent) {
+        if event.start_delay > Duration::ZERO {
+            println!("Event scheduled in {:?}: {event:?}", event.start_delay);
+            self.pending_events.push(event);
+            return;
+        }
+        //Queue up the next repeat before this one starts
+        if event.repeats_remaining > 0 && !event.persistent {
+            let mut next_event = event.clone();
+            next_event.repeats_remaining -= 1;
+            next_event.start_delay = next_event.repeat_interval;
+            self.pending_events.push(next_event);
+        }
         println!("Event added: {event:?}");
         //process the effects of adding this event
         //add initial value to effector
         match event.action {
-            BPActionType::Vibrate { strength, motor } => {
+            BPActionType::Vibrate { .. } => {
                 println!("Adding vibration event");
-                self.update_intensity_floor(motor, strength);
             }
-            BPActionType::Power { strength, motor } => {
+            BPActionType::Power { strength, .. } => {
                 println!("Adding vibration power event");
                 //End all other power events if intensity is 0
                 if strength == (0 as f64)
                 {
                     self.finish_power_events();
                 }
-                self.update_intensity_floor(motor, strength);
             }
-            BPActionType::Stop => {
+            BPActionType::Set { ref key, strength, motor } => {
+                println!("Setting keyed event {}", key);
+                if self.update_keyed_event(key, strength, motor) {
+                    return;
+                }
+            }
+            BPActionType::Clear { ref key } => {
+                println!("Clearing keyed event {}", key);
+                self.finish_keyed_events(key);
+                return;
+            }
+            BPActionType::Cancel { ref filter } => {
+                self.cancel_events(filter);
+                return;
+            }
+            BPActionType::Pause => {
+                self.set_paused(true);
+                return;
+            }
+            BPActionType::Resume => {
+                self.set_paused(false);
+                return;
+            }
+            BPActionType::Mix { policy, motor, ref tag } => {
+                match (motor, tag) {
+                    (_, Some(tag)) => self.set_tag_mix_policy(tag, Some(policy)),
+                    (Some(motor), None) => self.set_motor_mix_policy(motor, Some(policy)),
+                    (None, None) => self.set_mix_policy(policy),
+                }
+                return;
+            }
+            BPActionType::Stop | BPActionType::Panic => {
                 println!("Stop recieved, clearing all events and resetting all intensities");
                 self.force_stop();
                 return;
@@ -173,12 +698,6 @@ impl BPSimulator {
     }
     pub fn add_effector(&mut self, effector: BPEffector) {
         println!("Effector added: {effector:?}");
-        match effector.effector_type {
-            BPEffectorType::Vibrates { .. } => {
-                self.formula_floor_cache.insert(effector.index, 0 as f64)
-            }
-            BPEffectorType::Strokes { .. } => None,
-        };
         self.effectors.push(effector);
     }
     pub fn process_tick(&mut self, current_instant: Instant) {
@@ -193,12 +712,45 @@ impl BPSimulator {
             println!("Unusually long tick: {time_passed:?}");
         }
         self.last_sim_instant = current_instant;
+        //Time doesn't pass for events while paused
+        if self.paused {
+            self.unstepped_time = Duration::ZERO;
+            return;
+        }
+        //Always advance in whole fixed steps, leftover time carries over to the next tick
+        self.unstepped_time += time_passed;
+        if self.unstepped_time > SIM_MAX_CATCH_UP {
+            println!("Dropping {:?} of simulation time after a long gap", self.unstepped_time - SIM_MAX_CATCH_UP);
+            self.unstepped_time = SIM_MAX_CATCH_UP;
+        }
+        while self.unstepped_time >= self.time_step {
+            self.unstepped_time -= self.time_step;
+            self.step(self.time_step);
+        }
+    }
+    fn step(&mut self, time_passed: Duration) {
         //Run through and update effector states
         self.update_effectors(time_passed);
         //Update time remaining on events
         self.progress_event_times(time_passed);
         //Cull dead events
         self.cull_old_events();
+        //Start scheduled events whose delay has run out
+        self.progress_pending_events(time_passed);
+    }
+    fn progress_pending_events(&mut self, time_passed: Duration) {
+        let mut ready_events: Vec<BPSimEvent> = Vec::new();
+        let mut index = 0;
+        while index < self.pending_events.len() {
+            if self.pending_events[index].pass_delay(time_passed) {
+                ready_events.push(self.pending_events.remove(index));
+            } else {
+                index += 1;
+            }
+        }
+        for event in ready_events {
+            self.add_event(event);
+        }
     }
     fn progress_event_times(&mut self, time_passed: Duration) {
         for event in self.events.iter_mut() {
@@ -206,28 +758,14 @@ impl BPSimulator {
         }
     }
     fn update_effectors(&mut self, time_passed: Duration) {
-        for effector in self.effectors.iter_mut() {
+        //Floors come straight from the events active right now, so nothing can drift
+        let floors: Vec<f64> = self.effectors.iter().map(|effector| self.mix_motor(effector.index)).collect();
+        for (effector, floor) in self.effectors.iter_mut().zip(floors) {
             match effector.effector_type {
                 BPEffectorType::Vibrates { ref mut intensity } => {
-                    //Half life decay
-                    *intensity = BPSimulator::calc_intensity_decay(
-                        time_passed,
-                        *intensity,
-                        self.formula_linear_reduction_vib,
-                        self.formula_half_life_vib,
-                    );
-                    debug_assert!(
-                        match self.formula_floor_cache.get(&effector.index) {
-                            None => false,
-                            Some(_) => true,
-                        },
-                        "This vibrator was incorrectly initialized! It doesn't have a floor value."
-                    );
+                    *intensity = effector.decay_model.decay(time_passed, *intensity);
                     //Must be at minimum equal to currently active events
-                    *intensity = f64::max(
-                        *intensity,
-                        *(self.formula_floor_cache.get(&effector.index).unwrap()),
-                    );
+                    *intensity = f64::max(*intensity, floor);
                     if *intensity < self.formula_threshold
                     {
                         *intensity = 0 as f64;
@@ -240,40 +778,6 @@ impl BPSimulator {
         }
     }
     fn cull_old_events(&mut self) {
-        let mut index = 0;
-        //perform last actions of finished events
-        while index < self.events.len() {
-            match self.events.get(index) {
-                None => {
-                    println!("Error: index out of bounds during culling.")
-                }
-                Some(ev) => {
-                    if !ev.finished {
-                        index +=1;
-                        continue;
-                    }
-                }
-            }
-            match self.events.get(index).unwrap().action {
-                BPActionType::Vibrate { strength, motor } => {
-                    println!("Removing vibration event");
-                    self.update_intensity_floor(motor, -strength);
-                }
-                BPActionType::Power { strength, motor } => {
-                    println!("Removing vibration power event");
-                    self.update_intensity_floor(motor, -strength);
-                }
-                BPActionType::Stop => {
-                    println!("Stop recieved, clearing all events and resetting all intensities");
-                    self.force_stop();
-                    return;
-                }
-                BPActionType::Stroke => {
-                    println!("Stroke event not yet supported");
-                }
-            }
-          index +=1;
-        }
         //remove finished events
         let precull_event_count = self.events.len();
         self.events.retain(|ev: &BPSimEvent| !ev.finished);
@@ -304,22 +808,56 @@ impl BPSimulator {
         return new_intensity;
     }
 
-    fn update_intensity_floor(&mut self, index: i8, intensity_change: f64) {
-      println!("Updating intensity for motor {} by {}", index, intensity_change);
-        if index == -1 {
-            for (_, original_intensity) in self.formula_floor_cache.iter_mut() {
-                *original_intensity = f64::max(*original_intensity + intensity_change, 0 as f64);
+    //Combines the unfinished events driving one motor into its floor. Events with a tag that has its own policy are mixed
+    //together first and enter the motor's mix as one, at the highest priority among them.
+    pub fn mix_motor(&self, motor: i8) -> f64 {
+        let mut contributions: Vec<(f64, i32)> = Vec::new();
+        let mut tag_groups: HashMap<&str, Vec<(f64, i32)>> = HashMap::new();
+        for ev in self.events.iter().filter(|ev| !ev.finished) {
+            let (strength, ev_motor) = match ev.vibration() {
+                None => continue,
+                Some(vibration) => vibration,
+            };
+            if ev_motor != -1 && ev_motor != motor {
+                continue;
             }
-        } else {
-            match self.formula_floor_cache.get_mut(&index) {
-                None => {
-                    println!("Missing intensity floor for index {}", index)
-                }
-                Some(intensity) => {
-                    *intensity = f64::max(*intensity + intensity_change, 0 as f64);
-                }
+            match ev.tags.iter().find(|tag| self.tag_mix_policies.contains_key(*tag)) {
+                None => contributions.push((strength, ev.priority)),
+                Some(tag) => tag_groups.entry(tag.as_str()).or_default().push((strength, ev.priority)),
             }
         }
+        for (tag, group) in tag_groups {
+            let group_priority = group.iter().map(|(_, priority)| *priority).max().unwrap_or(0);
+            contributions.push((self.tag_mix_policies[tag].mix(&group, self.mix_duck_factor), group_priority));
+        }
+        self.motor_mix_policy(motor).mix(&contributions, self.mix_duck_factor)
+    }
+
+    pub fn motor_mix_policy(&self, motor: i8) -> BPMixPolicy {
+        *self.motor_mix_policies.get(&motor).unwrap_or(&self.mix_policy)
+    }
+
+    pub fn set_mix_policy(&mut self, policy: BPMixPolicy) {
+        println!("Default mix policy set to {:?}", policy);
+        self.mix_policy = policy;
+    }
+
+    //None goes back to the default policy
+    pub fn set_motor_mix_policy(&mut self, motor: i8, policy: Option<BPMixPolicy>) {
+        println!("Mix policy for motor {} set to {:?}", motor, policy);
+        match policy {
+            None => self.motor_mix_policies.remove(&motor),
+            Some(policy) => self.motor_mix_policies.insert(motor, policy),
+        };
+    }
+
+    //None mixes the tag's events individually again
+    pub fn set_tag_mix_policy(&mut self, tag: &str, policy: Option<BPMixPolicy>) {
+        println!("Mix policy for tag {} set to {:?}", tag, policy);
+        match policy {
+            None => self.tag_mix_policies.remove(tag),
+            Some(policy) => self.tag_mix_policies.insert(tag.to_string(), policy),
+        };
     }
 
     pub fn get_vibrator_intensities(&self) -> Vec<f64>
@@ -343,31 +881,83 @@ impl BPSimulator {
         }
       }
     //   println!("Intensities: {intensities:?}");
+      if self.paused && self.silence_when_paused
+      {
+        return vec![0.0; intensities.len()];
+      }
       return intensities;
     }
 
+    pub fn set_paused(&mut self, paused: bool)
+    {
+        if self.paused != paused
+        {
+            println!("Simulation {}", if paused { "paused" } else { "resumed" });
+        }
+        self.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool
+    {
+        self.paused
+    }
+
+    //Paused with all vibrators reported as stopped
+    pub fn is_silenced(&self) -> bool
+    {
+        self.paused && self.silence_when_paused
+    }
+
     pub fn finish_power_events(&mut self){
-        let mut index = 0;
-        //perform last actions of finished events
-        while index < self.events.len() {
-            match self.events.get_mut(index) {
-                None => {
-                    println!("Error: index out of bounds during culling.")
+        self.cancel_events(&BPCancelFilter::Type("POWER".to_string()));
+    }
+
+    //Finishes matching active events and drops matching scheduled ones
+    pub fn cancel_events(&mut self, filter: &BPCancelFilter) {
+        let mut cancelled_count = 0;
+        for ev in self.events.iter_mut() {
+            if !ev.finished && ev.matches_filter(filter) {
+                ev.force_finish();
+                cancelled_count += 1;
+            }
+        }
+        let pending_count = self.pending_events.len();
+        self.pending_events.retain(|ev: &BPSimEvent| !ev.matches_filter(filter));
+        cancelled_count += pending_count - self.pending_events.len();
+        println!("Cancelled {} events matching {:?}", cancelled_count, filter);
+    }
+
+    //Updates an active keyed event in place. Returns false if there is no event with that key.
+    fn update_keyed_event(&mut self, key: &str, new_strength: f64, new_motor: i8) -> bool {
+        match self.events.iter_mut().find(|ev| {
+            !ev.finished && matches!(ev.action, BPActionType::Set { key: ref ev_key, .. } if ev_key == key)
+        }) {
+            None => false,
+            Some(ev) => match ev.action {
+                BPActionType::Set { ref mut strength, ref mut motor, .. } => {
+                    *strength = new_strength;
+                    *motor = new_motor;
+                    true
                 }
-                Some(ev) => {
-                    index +=1;
-                    if ev.finished {
-                        continue;
-                    }
-                    match ev.action{
-                        BPActionType::Power{..} => {
-                        },
-                        _ => continue,
-                    }
+                _ => false,
+            },
+        }
+    }
+
+    pub fn has_keyed_event(&self, key: &str) -> bool {
+        self.events.iter().any(|ev| {
+            !ev.finished && matches!(ev.action, BPActionType::Set { key: ref ev_key, .. } if ev_key == key)
+        })
+    }
+
+    //Finishes keyed events
+    pub fn finish_keyed_events(&mut self, key: &str) {
+        for ev in self.events.iter_mut() {
+            if let BPActionType::Set { key: ref ev_key, .. } = ev.action {
+                if ev_key == key {
                     ev.force_finish();
                 }
             }
-          index +=1;
         }
     }
 
@@ -375,9 +965,13 @@ impl BPSimulator {
     pub fn force_stop(&mut self) {
         println!("Force stopping");
         println!("Events to remove: {}", self.events.len());
+        println!("Scheduled events to remove: {}", self.pending_events.len());
         self.events.clear();
-        for (_, intensity) in self.formula_floor_cache.iter_mut() {
-            *intensity = 0 as f64;
+        self.pending_events.clear();
+        for effector in self.effectors.iter_mut() {
+            if let BPEffectorType::Vibrates { ref mut intensity } = effector.effector_type {
+                *intensity = 0 as f64;
+            }
         }
         //TODO: Force stop for other components
     }
@@ -385,8 +979,21 @@ impl BPSimulator {
     pub fn reset_for_new_device(&mut self)
     {
       self.force_stop();
-      self.effectors.clear();
-      self.formula_floor_cache.clear();
+      for effector in self.effectors.drain(..) {
+        self.saved_decay_models.insert(effector.index, effector.decay_model);
+      }
+    }
+
+    pub fn set_decay_model(&mut self, motor: i8, decay_model: Box<dyn BPDecayModel>)
+    {
+        match self.effectors.iter_mut().find(|effector| effector.index == motor)
+        {
+            None => println!("No effector {} to set the decay model of", motor),
+            Some(effector) => {
+                println!("Decay model for motor {} set to {}", motor, decay_model.name());
+                effector.decay_model = decay_model;
+            }
+        }
     }
 
     pub fn add_multiple_vib_effectors(&mut self, num_motors: usize)
@@ -395,7 +1002,12 @@ impl BPSimulator {
         let mut vib_index: usize = 0;
         while vib_index < num_motors
         {
-            self.add_effector(BPEffector::new(BPEffectorType::Vibrates { intensity: 0 as f64 }, vib_index as i8));
+            let mut effector = BPEffector::new(BPEffectorType::Vibrates { intensity: 0 as f64 }, vib_index as i8);
+            if let Some(decay_model) = self.saved_decay_models.remove(&effector.index)
+            {
+                effector.decay_model = decay_model;
+            }
+            self.add_effector(effector);
             vib_index += 1;
         }
         //TODO: Other effectors
@@ -411,32 +1023,142 @@ impl BPSimulator {
         }
     }
 }
+//Estimates how fast the game runs compared to wall time, so frame counts can be turned into real durations
 #[derive(Debug)]
-pub struct BPCommand
+pub struct BPGameClock
 {
-    game_frame : u64,
-    event_name : String,
-    command_args : HashMap<String, f64>,
+    //Frame and time of the last measurement
+    anchor: Option<(u64, Instant)>,
+    estimated_speed: f64,
+    samples: u32,
+    //Speed given by a GAMESPEED command, used instead of the estimate
+    speed_override: Option<f64>,
+    //Newest frame and the time the game wrote it, maps other frames onto wall time
+    latest_write: Option<(u64, Instant)>,
 }
 
-impl BPCommand
+impl Default for BPGameClock
 {
-    pub fn new(command_string : String) -> Option<BPCommand>
+    fn default() -> BPGameClock
     {
-        // println!("Converting {} into a command", command_string);
-        
-        if command_string == ""
+        BPGameClock
         {
-            println!("This string is empty!");
-            return None;
+            anchor: None,
+            estimated_speed: 1.0,
+            samples: 0,
+            speed_override: None,
+            latest_write: None,
         }
-        if command_string == "\n"
+    }
+}
+
+impl BPGameClock
+{
+    pub fn new() -> BPGameClock
+    {
+        Default::default()
+    }
+
+    pub fn observe_frame(&mut self, frame: u64, now: Instant)
+    {
+        let (anchor_frame, anchor_instant) = match self.anchor
         {
-            println!("This string is a single newline!");
-            return None;
-        }
-        let mut cmd_iter = command_string.split_whitespace();
-        
+            Some(anchor) if frame > anchor.0 => anchor,
+            _ => {
+                self.anchor = Some((frame, now));
+                return;
+            }
+        };
+        let elapsed = now.saturating_duration_since(anchor_instant);
+        if elapsed < GAME_SPEED_SAMPLE_WINDOW
+        {
+            return;
+        }
+        let measured_speed = (frame - anchor_frame) as f64 / GAME_FRAMES_PER_SECOND / elapsed.as_secs_f64();
+        self.estimated_speed = if self.samples == 0
+        {
+            measured_speed
+        }
+        else
+        {
+            self.estimated_speed + (measured_speed - self.estimated_speed) * GAME_SPEED_SMOOTHING
+        };
+        self.estimated_speed = self.estimated_speed.clamp(GAME_SPEED_MIN, GAME_SPEED_MAX);
+        self.samples += 1;
+        self.anchor = Some((frame, now));
+    }
+
+    pub fn set_speed_override(&mut self, speed: Option<f64>)
+    {
+        let speed = speed.map(|speed| speed.clamp(GAME_SPEED_MIN, GAME_SPEED_MAX));
+        println!("Game speed set to {:?}", speed);
+        self.speed_override = speed;
+    }
+
+    pub fn game_speed(&self) -> f64
+    {
+        self.speed_override.unwrap_or(self.estimated_speed)
+    }
+
+    //None when the frame count doesn't make a valid duration, like negative, NaN or absurdly large ones
+    pub fn frames_to_duration(&self, frames: f64) -> Option<Duration>
+    {
+        Duration::try_from_secs_f64(frames / (GAME_FRAMES_PER_SECOND * self.game_speed())).ok()
+    }
+
+    pub fn observe_latest_write(&mut self, frame: u64, write_instant: Instant)
+    {
+        self.latest_write = Some((frame, write_instant));
+    }
+
+    //How long ago the game was at this frame
+    pub fn frame_age(&self, frame: u64, now: Instant) -> Duration
+    {
+        match self.latest_write
+        {
+            None => Duration::ZERO,
+            Some((latest_frame, write_instant)) => {
+                let since_write = now.saturating_duration_since(write_instant);
+                let frames_behind = self.frames_to_duration(latest_frame.saturating_sub(frame) as f64).unwrap_or(Duration::MAX);
+                since_write.saturating_add(frames_behind)
+            }
+        }
+    }
+
+    pub fn reset(&mut self)
+    {
+        *self = BPGameClock::new();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BPCommand
+{
+    game_frame : u64,
+    event_name : String,
+    command_args : HashMap<String, f64>,
+    //Arguments whose value isn't a number, such as Key:eco_stall
+    command_str_args : HashMap<String, String>,
+}
+
+impl BPCommand
+{
+    pub fn new(command_string : String) -> Option<BPCommand>
+    {
+        // println!("Converting {} into a command", command_string);
+        
+        if command_string == ""
+        {
+            println!("This string is empty!");
+            return None;
+        }
+        if command_string == "\n"
+        {
+            println!("This string is a single newline!");
+            return None;
+        }
+        let mut cmd_iter = command_string.split_whitespace();
+        
         let frame = match cmd_iter.next()
         {
             None => {
@@ -468,6 +1190,7 @@ impl BPCommand
             }
         };
         let mut cmd_args: HashMap<String, f64> = HashMap::new();
+        let mut cmd_str_args: HashMap<String, String> = HashMap::new();
         for arg_str in cmd_iter
         {
             let mut arg_iter = arg_str.split(":");
@@ -491,7 +1214,7 @@ impl BPCommand
                     match arg_value_string.parse::<f64>()
                     {
                         Err(_) => {
-                            println!("Error occured when trying to parse {} as an f64", arg_value_string);
+                            cmd_str_args.insert(arg_name.to_string(), arg_value_string.to_string());
                             continue;
                         },
                         Ok(arg_val) => {
@@ -507,10 +1230,199 @@ impl BPCommand
             game_frame: frame,
             event_name: event_name,
             command_args : cmd_args,
+            command_str_args : cmd_str_args,
         })
     }
 
+    //Text arguments that happen to look like numbers end up in command_args, so check both
+    fn get_str_arg(&self, arg_name: &str) -> Option<String>
+    {
+        match self.command_str_args.get(arg_name)
+        {
+            Some(arg_val) => Some(arg_val.clone()),
+            None => self.command_args.get(arg_name).map(|arg_val| arg_val.to_string()),
+        }
+    }
+
+    //Converts frame based arguments assuming the game runs at normal speed
     pub fn to_event(&self) -> Option<BPSimEvent>
+    {
+        self.to_timed_event(&BPGameClock::new())
+    }
+
+    //Builds the event, then applies the scheduling arguments shared by all commands:
+    //Delay (seconds) or DelayFrames (game frames after this command's frame),
+    //Repeat (total number of times the event is started) and Interval (seconds between starts).
+    //Priority is used when mixing with the priority ducking policy.
+    pub fn to_timed_event(&self, game_clock: &BPGameClock) -> Option<BPSimEvent>
+    {
+        let mut event = self.to_unscheduled_event(game_clock)?;
+        if !self.apply_scheduling_args(&mut event, game_clock)
+        {
+            return None;
+        }
+        Some(event)
+    }
+
+    //Sets the handles and scheduling from this command's arguments, false if one of them is invalid
+    fn apply_scheduling_args(&self, event: &mut BPSimEvent, game_clock: &BPGameClock) -> bool
+    {
+        event.id = self.get_str_arg("Id");
+        //Several tags can be given separated by commas, Tag:attack,base
+        if let Some(tag_list) = self.get_str_arg("Tag")
+        {
+            event.tags = tag_list.split(',').filter(|tag| !tag.is_empty()).map(|tag| tag.to_string()).collect();
+        }
+        if let Some(priority) = self.command_args.get("Priority")
+        {
+            event.priority = *priority as i32;
+        }
+        if let Some(seconds) = self.command_args.get("Delay")
+        {
+            if *seconds < 0.0
+            {
+                println!("Cannot create an event with a negative delay");
+                return false;
+            }
+            event.start_delay = match Duration::try_from_secs_f64(*seconds)
+            {
+                Err(_) => {
+                    println!("Cannot create an event with a delay of {} seconds", seconds);
+                    return false;
+                }
+                Ok(delay) => delay,
+            };
+        }
+        if let Some(frames) = self.command_args.get("DelayFrames")
+        {
+            if *frames < 0.0
+            {
+                println!("Cannot create an event with a negative delay");
+                return false;
+            }
+            match game_clock.frames_to_duration(*frames).and_then(|delay| event.start_delay.checked_add(delay))
+            {
+                None => {
+                    println!("Cannot create an event with a delay of {} frames", frames);
+                    return false;
+                }
+                Some(delay) => event.start_delay = delay,
+            }
+        }
+        if let Some(repeat_count) = self.command_args.get("Repeat")
+        {
+            if *repeat_count < 1.0
+            {
+                println!("Cannot repeat an event less than once");
+                return false;
+            }
+            event.repeats_remaining = *repeat_count as u32 - 1;
+            //Back to back repeats unless told otherwise
+            event.repeat_interval = match self.command_args.get("Interval")
+            {
+                None => event.time_remaining,
+                Some(seconds) => {
+                    if *seconds < 0.0
+                    {
+                        println!("Cannot repeat an event with a negative interval");
+                        return false;
+                    }
+                    match Duration::try_from_secs_f64(*seconds)
+                    {
+                        Err(_) => {
+                            println!("Cannot repeat an event with an interval of {} seconds", seconds);
+                            return false;
+                        }
+                        Ok(interval) => interval,
+                    }
+                }
+            };
+        }
+        true
+    }
+
+    //Like to_timed_event, but resolves config-defined macros into the events of each of their steps
+    pub fn to_events(&self, macros: &BPMacroTable, game_clock: &BPGameClock) -> Vec<BPSimEvent>
+    {
+        self.to_events_with_depth(macros, game_clock, 0)
+    }
+
+    fn to_events_with_depth(&self, macros: &BPMacroTable, game_clock: &BPGameClock, depth: u32) -> Vec<BPSimEvent>
+    {
+        match macros.expand(self)
+        {
+            None => match self.to_timed_event(game_clock)
+            {
+                None => {
+                    println!("Could not convert {self:?} into command");
+                    Vec::new()
+                },
+                Some(bpevent) => vec![bpevent],
+            },
+            Some(steps) => {
+                if depth >= MACRO_MAX_DEPTH
+                {
+                    println!("Macro {} nested too deeply, skipping", self.event_name);
+                    return Vec::new();
+                }
+                let mut bpevents: Vec<BPSimEvent> = steps.iter().flat_map(|step| step.to_events_with_depth(macros, game_clock, depth + 1)).collect();
+                if !self.apply_macro_call_args(macros, &mut bpevents, game_clock)
+                {
+                    println!("Invalid scheduling arguments on macro {}, skipping", self.event_name);
+                    return Vec::new();
+                }
+                bpevents
+            }
+        }
+    }
+
+    //Handles and scheduling given on a macro call act on every step, unless the macro passes them on itself
+    fn apply_macro_call_args(&self, macros: &BPMacroTable, bpevents: &mut [BPSimEvent], game_clock: &BPGameClock) -> bool
+    {
+        let mut call_args = self.clone();
+        for arg_name in ["Tag", "Id", "Priority", "Delay", "DelayFrames", "Repeat", "Interval"]
+        {
+            if macros.references(&self.event_name, arg_name)
+            {
+                call_args.command_args.remove(arg_name);
+                call_args.command_str_args.remove(arg_name);
+            }
+        }
+        let mut schedule = BPSimEvent::new(Duration::ZERO, BPActionType::Stop);
+        if !call_args.apply_scheduling_args(&mut schedule, game_clock)
+        {
+            return false;
+        }
+        //Repeats start the whole macro again once its last step is over
+        let macro_span = bpevents.iter().map(|bpevent| bpevent.start_delay + bpevent.time_remaining).max().unwrap_or(Duration::ZERO);
+        for bpevent in bpevents.iter_mut()
+        {
+            for tag in schedule.tags.iter()
+            {
+                if !bpevent.tags.contains(tag)
+                {
+                    bpevent.tags.push(tag.clone());
+                }
+            }
+            if schedule.id.is_some()
+            {
+                bpevent.id.clone_from(&schedule.id);
+            }
+            if call_args.command_args.contains_key("Priority")
+            {
+                bpevent.priority = schedule.priority;
+            }
+            bpevent.start_delay += schedule.start_delay;
+            if call_args.command_args.contains_key("Repeat")
+            {
+                bpevent.repeats_remaining = schedule.repeats_remaining;
+                bpevent.repeat_interval = if call_args.command_args.contains_key("Interval") { schedule.repeat_interval } else { macro_span };
+            }
+        }
+        true
+    }
+
+    fn to_unscheduled_event(&self, game_clock: &BPGameClock) -> Option<BPSimEvent>
     {
         match self.event_name.as_str()
         {
@@ -519,19 +1431,39 @@ impl BPCommand
                 return Some(BPSimEvent::new_stop_event());
             }
             "VIBRATE" => {
-                let duration: Duration = match self.command_args.get("Duration")
+                //DurationFrames is measured in game frames, so it follows the game speed
+                let duration_arg = match self.command_args.get("DurationFrames")
+                {
+                    None => self.command_args.get("Duration").copied(),
+                    Some(frames) => match game_clock.frames_to_duration(*frames)
+                    {
+                        None => {
+                            println!("Cannot create VIBRATE command lasting {} frames", frames);
+                            return None;
+                        }
+                        Some(duration) => Some(duration.as_secs_f64()),
+                    },
+                };
+                let duration: Duration = match duration_arg
                 {
                     None =>{
                         println!("Cannot create VIBRATE command as it lacks a duration");
                         return None;
                     }
                     Some(seconds) => {
-                        if(*seconds < 0 as f64)
+                        if(seconds < 0 as f64)
                         {
                             println!("Cannot create an event with negative lifespan");
                             return None;
                         }
-                        Duration::from_secs_f64(*seconds)
+                        match Duration::try_from_secs_f64(seconds)
+                        {
+                            Err(_) => {
+                                println!("Cannot create an event lasting {} seconds", seconds);
+                                return None;
+                            }
+                            Ok(duration) => duration,
+                        }
                     }
                 };
                 let strength: f64 = match self.command_args.get("Strength")
@@ -577,7 +1509,90 @@ impl BPCommand
                         *m_index as i8
                     }
                 };
-                return Some(BPSimEvent::new(EVENT_POWER_DURATION, BPActionType::Power { strength: strength, motor: motor_index }));
+                return Some(BPSimEvent::new_persistent(BPActionType::Power { strength: strength, motor: motor_index }));
+            },
+            "SET" =>{
+                let key: String = match self.get_str_arg("Key")
+                {
+                    None => {
+                        println!("Cannot create SET command as it lacks a key");
+                        return None;
+                    }
+                    Some(key_val) =>{
+                        key_val
+                    }
+                };
+                let strength: f64 = match self.command_args.get("Strength")
+                {
+                    None => {
+                        println!("Cannot create SET command as it lacks a strength");
+                        return None;
+                    }
+                    Some(strength_val) =>{
+                        *strength_val
+                    }
+                };
+                //Keyed events affect every motor unless told otherwise
+                let motor_index: i8 = match self.command_args.get("Motor")
+                {
+                    None => -1,
+                    Some(m_index) => *m_index as i8,
+                };
+                Some(BPSimEvent::new_persistent(BPActionType::Set { key, strength, motor: motor_index }))
+            },
+            "CANCEL" =>{
+                let filter = if let Some(id) = self.get_str_arg("Id")
+                {
+                    BPCancelFilter::Id(id)
+                }
+                else if let Some(tag) = self.get_str_arg("Tag")
+                {
+                    BPCancelFilter::Tag(tag)
+                }
+                else if let Some(type_name) = self.get_str_arg("Type")
+                {
+                    BPCancelFilter::Type(type_name.to_uppercase())
+                }
+                else
+                {
+                    println!("Cannot create CANCEL command as it lacks an Id, Tag or Type");
+                    return None;
+                };
+                Some(BPSimEvent::new(Duration::ZERO, BPActionType::Cancel { filter }))
+            },
+            "PAUSE" =>{
+                Some(BPSimEvent::new(Duration::ZERO, BPActionType::Pause))
+            },
+            "RESUME" =>{
+                Some(BPSimEvent::new(Duration::ZERO, BPActionType::Resume))
+            },
+            "PANIC" =>{
+                Some(BPSimEvent::new(Duration::ZERO, BPActionType::Panic))
+            },
+            "MIX" =>{
+                let policy = match self.get_str_arg("Policy").as_deref().and_then(BPMixPolicy::from_name)
+                {
+                    None => {
+                        println!("Cannot create MIX command as it lacks a known policy");
+                        return None;
+                    }
+                    Some(policy) => policy,
+                };
+                //Motor:-1 means every motor, which is what the default policy covers
+                let motor = self.command_args.get("Motor").map(|m_index| *m_index as i8).filter(|m_index| *m_index >= 0);
+                Some(BPSimEvent::new(Duration::ZERO, BPActionType::Mix { policy, motor, tag: self.get_str_arg("Tag") }))
+            },
+            "CLEAR" =>{
+                match self.get_str_arg("Key")
+                {
+                    None => {
+                        println!("Cannot create CLEAR command as it lacks a key");
+                        None
+                    }
+                    Some(key_val) =>{
+                        Some(BPSimEvent::new(Duration::ZERO, BPActionType::Clear { key: key_val }))
+                    }
+                }
             },
             _ =>{
                 println!("Unrecognized command: {}", self.event_name);
@@ -587,33 +1602,31 @@ impl BPCommand
     }
 }
 
-pub struct BPDataParser {
-    file_path : PathBuf,
-    prev_reached_frame : u64,
-    first_read: bool
+const MACRO_FILE_PATH: &str = "bab_macros.txt";
+//Maximum depth of macros expanding into other macros, stops self-referencing macros looping forever
+const MACRO_MAX_DEPTH: u32 = 8;
+
+//Config-defined commands that expand into a sequence of primitive commands.
+//Each line of the macro file is one step: MACRO_NAME COMMAND Arg:Value ...
+//A value of $Param is replaced by the caller's Param argument, $Param*0.5 scales it.
+//Steps usually use Delay to build a timed sequence. Lines starting with # are comments.
+#[derive(Debug, Default)]
+pub struct BPMacroTable
+{
+    macros: HashMap<String, Vec<String>>,
 }
 
-impl BPDataParser
+impl BPMacroTable
 {
-    pub fn new(file_address: String) -> BPDataParser
+    pub fn new() -> BPMacroTable
     {
-        let path = Path::new(&file_address).to_path_buf();
-        
-        return BPDataParser
-        {
-            file_path: path,
-            prev_reached_frame: 0,
-            first_read: true,
-        }
+        Default::default()
     }
 
-    pub fn get_new_events(&mut self) -> VecDeque<BPSimEvent>
+    pub fn load(file_address: &str) -> BPMacroTable
     {
-        let mut event_queue:VecDeque<BPSimEvent> = VecDeque::new();
-        let mut current_end_frame:u64 = u64::MIN;
-        //TODO
-        // println!("Opening file {}", self.file_path.to_string_lossy());
-        let mut new_file = match File::open(self.file_path.as_path())
+        let mut macro_table = BPMacroTable::new();
+        let mut macro_file = match File::open(Path::new(file_address))
         {
             Ok(file) => file,
             Err(e) => {
@@ -621,89 +1634,492 @@ impl BPDataParser
                 {
                     io::ErrorKind::NotFound =>
                     {
-                        println!("ERROR: File {} not found", self.file_path.to_string_lossy());
+                        println!("No macro file found at {}, macros disabled", file_address);
                     },
                     io::ErrorKind::PermissionDenied =>
                     {
-                        println!("ERROR: No permission to access {}", self.file_path.to_string_lossy());
+                        println!("ERROR: No permission to access {}", file_address);
                     },
-                    _ => 
+                    _ =>
                     {
                         println!("ERROR: Some other unknown error: {}", e);
                     }
                 }
-                return event_queue;
+                return macro_table;
             }
         };
-        let rev_lines = RevLines::new(new_file);
-        let mut events_read = 0;
-        for line_res in rev_lines {
-            let line:String = match line_res {
-                Err(ref why) => {
-                    println!("Error when reading {}: {}", self.file_path.to_string_lossy(), why);
-                    return event_queue;
-                },
-                Ok(line_str) => {
-                    // println!("{}", line_str);
-                    line_str
-                },
-            };
-            let command = BPCommand::new(line);
-            events_read +=1;
-            match command {
-                None => {
-                    println!("Could not parse command");
-                },
-                Some(cmd) => {
-                    current_end_frame = u64::max(current_end_frame, cmd.game_frame);
-                    if  cmd.game_frame <= self.prev_reached_frame
-                    {
-                        //Edge case: most recent command is earlier than the furthest reached -> new game has happened
-                        if events_read == 1 && cmd.game_frame < self.prev_reached_frame
-                        {
-                            println!("Most recent command goes back in time. Assuming new game has occured and resetting frame counter");
-                            self.reset_state_for_new_game(cmd.game_frame);
-                        }
-                        //Edge case: if there is an event on the initial frame (0), don't skip it.
-                        if self.first_read && self.prev_reached_frame == cmd.game_frame
-                        {
-                            println!("Reached front of first read");
-                        }
-                        else {
-                            // println!("Reached end of new events.");
-                            break;
-                        }
-                    }
-                    match cmd.to_event()
-                    {
-                        None => {
-                            println!("Could not convert {cmd:?} into command");
-                        }
-                        Some(bpevent) => {
-                            event_queue.push_front(bpevent);
-                        }
-                    }
-                }
-            }
-        }
-        self.prev_reached_frame = current_end_frame;
-        if events_read >= 1
+        let mut s = String::new();
+        if let Err(why) = macro_file.read_to_string(&mut s)
         {
-            self.first_read = false;
+            println!("couldn't read {}: {}", file_address, why);
+            return macro_table;
         }
-        if event_queue.len() != 0
+        for line in s.lines()
         {
-            println!("Total new events: {}", event_queue.len())
+            macro_table.add_macro_line(line);
         }
-        return event_queue;
+        println!("Loaded {} macros from {}", macro_table.macros.len(), file_address);
+        macro_table
     }
 
-    pub fn debug_print_file(&mut self)
+    pub fn add_macro_line(&mut self, line: &str)
     {
-        println!("Opening file {}", self.file_path.to_string_lossy());
-        let mut new_file = match File::open(self.file_path.as_path())
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#')
         {
-            Ok(file) => file,
+            return;
+        }
+        match line.split_once(char::is_whitespace)
+        {
+            None => {
+                println!("ERROR: Macro {} has no command", line);
+            },
+            Some((macro_name, step)) => {
+                self.macros.entry(macro_name.to_string()).or_default().push(step.trim().to_string());
+            }
+        }
+    }
+
+    //Whether any step of the macro takes the caller's argument, as in Delay:$Delay
+    pub fn references(&self, macro_name: &str, arg_name: &str) -> bool
+    {
+        self.macros.get(macro_name).is_some_and(|steps| steps.iter().any(|step| {
+            step.split_whitespace()
+                .filter_map(|arg_str| arg_str.split_once(":$"))
+                .any(|(_, param)| param.split('*').next() == Some(arg_name))
+        }))
+    }
+
+    //Turns a macro call into its steps, substituting the caller's arguments. None if it isn't a macro.
+    pub fn expand(&self, command: &BPCommand) -> Option<Vec<BPCommand>>
+    {
+        let steps = self.macros.get(&command.event_name)?;
+        let mut expanded: Vec<BPCommand> = Vec::new();
+        for step in steps
+        {
+            let mut step_string = command.game_frame.to_string();
+            let mut step_iter = step.split_whitespace();
+            if let Some(step_name) = step_iter.next()
+            {
+                step_string.push(' ');
+                step_string.push_str(step_name);
+            }
+            for arg_str in step_iter
+            {
+                step_string.push(' ');
+                match arg_str.split_once(":$")
+                {
+                    None => step_string.push_str(arg_str),
+                    Some((arg_name, param)) => {
+                        match BPMacroTable::substitute_param(command, param)
+                        {
+                            None => {
+                                println!("Macro {} is missing argument {} from {:?}", command.event_name, param, command);
+                                step_string.push_str(arg_name);
+                                continue;
+                            },
+                            Some(value) => {
+                                step_string.push_str(arg_name);
+                                step_string.push(':');
+                                step_string.push_str(&value);
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(step_command) = BPCommand::new(step_string)
+            {
+                expanded.push(step_command);
+            }
+        }
+        Some(expanded)
+    }
+
+    fn substitute_param(command: &BPCommand, param: &str) -> Option<String>
+    {
+        let (param_name, factor) = match param.split_once('*')
+        {
+            None => (param, None),
+            Some((param_name, factor_str)) => match factor_str.parse::<f64>()
+            {
+                Err(_) => {
+                    println!("Error occured when trying to parse {} as an f64", factor_str);
+                    return None;
+                },
+                Ok(factor) => (param_name, Some(factor)),
+            },
+        };
+        match (command.command_args.get(param_name), factor)
+        {
+            (Some(value), Some(factor)) => Some((value * factor).to_string()),
+            (Some(value), None) => Some(value.to_string()),
+            (None, None) => command.command_str_args.get(param_name).cloned(),
+            (None, Some(_)) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BPStaleCommandPolicy
+{
+    Keep,
+    Drop,
+    //Start the event part way through, as if it had been read on time
+    Compress,
+}
+
+//Decides which commands in a shared log are meant for this user.
+//Commands without Player: or Team: arguments are for everyone.
+#[derive(Debug, Default)]
+pub struct BPRoutingFilter
+{
+    //Empty means this client takes every player's commands
+    pub player_id: String,
+    pub team_id: String,
+    //Also take commands sent to the whole of this user's team
+    pub receive_team_events: bool,
+}
+
+impl BPRoutingFilter
+{
+    pub fn accepts(&self, cmd: &BPCommand) -> bool
+    {
+        if let Some(player) = cmd.get_str_arg("Player")
+        {
+            return self.player_id.is_empty() || player == self.player_id.trim();
+        }
+        if let Some(team) = cmd.get_str_arg("Team")
+        {
+            return self.player_id.is_empty() || (self.receive_team_events && team == self.team_id.trim());
+        }
+        true
+    }
+}
+
+//One game, from GAMESTART (or the first sign of a new game) to GAMEOVER
+#[derive(Debug)]
+pub struct BPSession
+{
+    pub number: u32,
+    pub active: bool,
+    //Started by a GAMESTART command rather than guessed from the log
+    pub explicit: bool,
+    pub start_frame: u64,
+    pub started_at: Instant,
+    pub ended_at: Option<Instant>,
+    //Ended by the input watchdog rather than GAMEOVER, picks up again if commands come back
+    pub timed_out: bool,
+    //Arguments given to GAMESTART, such as Map:
+    pub metadata: HashMap<String, String>,
+}
+
+impl BPSession
+{
+    pub fn new(number: u32, start_frame: u64, metadata: HashMap<String, String>, explicit: bool) -> BPSession
+    {
+        BPSession
+        {
+            number,
+            active: true,
+            explicit,
+            start_frame,
+            started_at: Instant::now(),
+            ended_at: None,
+            timed_out: false,
+            metadata,
+        }
+    }
+}
+
+pub struct BPDataParser {
+    file_path : PathBuf,
+    prev_reached_frame : u64,
+    //Bytes of the file already handled, new commands are read from here
+    read_offset: u64,
+    file_created: Option<SystemTime>,
+    session: BPSession,
+    pub routing: BPRoutingFilter,
+    macros: BPMacroTable,
+    game_clock: BPGameClock,
+    //What to do with short lived commands read more than max_command_age after they were written
+    pub stale_policy: BPStaleCommandPolicy,
+    pub max_command_age: Duration,
+    //Catching up on the current game is skipped when the file is older than this
+    pub max_catch_up_age: Duration,
+    //Age of the newest command when it was read
+    input_lag: Duration,
+    //Last time a command with a later game frame was read
+    last_frame_progress_instant: Instant,
+    last_modified: Option<SystemTime>,
+    //Last time the file was written to or the game frame moved on
+    last_input_instant: Instant,
+}
+
+impl BPDataParser
+{
+    pub fn new(file_address: String) -> BPDataParser
+    {
+        let path = Path::new(&file_address).to_path_buf();
+        
+        return BPDataParser
+        {
+            file_path: path,
+            prev_reached_frame: 0,
+            read_offset: 0,
+            file_created: None,
+            session: BPSession::new(0, 0, HashMap::new(), false),
+            routing: BPRoutingFilter::default(),
+            macros: BPMacroTable::new(),
+            game_clock: BPGameClock::new(),
+            stale_policy: BPStaleCommandPolicy::Compress,
+            max_command_age: Duration::from_millis(500),
+            max_catch_up_age: Duration::from_secs(60),
+            input_lag: Duration::ZERO,
+            last_frame_progress_instant: Instant::now(),
+            last_modified: None,
+            last_input_instant: Instant::now(),
+        }
+    }
+
+    pub fn set_macros(&mut self, macros: BPMacroTable)
+    {
+        self.macros = macros;
+    }
+
+    pub fn get_new_events(&mut self) -> VecDeque<BPSimEvent>
+    {
+        let mut event_queue:VecDeque<BPSimEvent> = VecDeque::new();
+        // println!("Opening file {}", self.file_path.to_string_lossy());
+        let mut new_file = match File::open(self.file_path.as_path())
+        {
+            Ok(file) => file,
+            Err(e) => {
+                match e.kind()
+                {
+                    io::ErrorKind::NotFound =>
+                    {
+                        println!("ERROR: File {} not found", self.file_path.to_string_lossy());
+                    },
+                    io::ErrorKind::PermissionDenied =>
+                    {
+                        println!("ERROR: No permission to access {}", self.file_path.to_string_lossy());
+                    },
+                    _ => 
+                    {
+                        println!("ERROR: Some other unknown error: {}", e);
+                    }
+                }
+                return event_queue;
+            }
+        };
+        let read_instant = Instant::now();
+        let metadata = match new_file.metadata()
+        {
+            Err(why) => {
+                println!("Error when reading {}: {}", self.file_path.to_string_lossy(), why);
+                return event_queue;
+            },
+            Ok(metadata) => metadata,
+        };
+        //A shorter or recreated file means the game has started a new log
+        let file_created = metadata.created().ok();
+        if metadata.len() < self.read_offset || (self.file_created.is_some() && file_created != self.file_created)
+        {
+            println!("{} was truncated or replaced, assuming a new game has started", self.file_path.to_string_lossy());
+            self.read_offset = 0;
+            self.start_session(0, HashMap::new(), false, &mut event_queue);
+        }
+        self.file_created = file_created;
+        let modified = metadata.modified().ok();
+        if modified != self.last_modified
+        {
+            self.last_modified = modified;
+            self.note_input();
+        }
+        if metadata.len() == self.read_offset
+        {
+            return event_queue;
+        }
+        //Instant the game last wrote to the file, used to work out how old the commands are
+        let write_instant = match metadata.modified()
+        {
+            Err(_) => read_instant,
+            Ok(modified) => {
+                let write_age = SystemTime::now().duration_since(modified).unwrap_or(Duration::ZERO);
+                read_instant.checked_sub(write_age).unwrap_or(read_instant)
+            }
+        };
+        //Only read what has been added since last time
+        let mut new_text = String::new();
+        if let Err(why) = new_file.seek(io::SeekFrom::Start(self.read_offset)).and_then(|_| new_file.read_to_string(&mut new_text))
+        {
+            println!("Error when reading {}: {}", self.file_path.to_string_lossy(), why);
+            return event_queue;
+        }
+        //The game may be partway through writing the last line, leave it for next time
+        let complete_len = match new_text.rfind('\n')
+        {
+            None => return event_queue,
+            Some(newline_index) => newline_index + 1,
+        };
+        self.read_offset += complete_len as u64;
+        let mut commands: Vec<BPCommand> = Vec::new();
+        for line in new_text[..complete_len].lines().filter(|line| !line.trim().is_empty())
+        {
+            match BPCommand::new(line.to_string())
+            {
+                None => {
+                    println!("Could not parse command");
+                },
+                Some(cmd) => commands.push(cmd),
+            }
+        }
+        let newest_frame = match commands.last()
+        {
+            None => return event_queue,
+            Some(cmd) => cmd.game_frame,
+        };
+        //The newest command was written when the file was last modified
+        self.game_clock.observe_latest_write(newest_frame, write_instant);
+        self.input_lag = self.game_clock.frame_age(newest_frame, read_instant);
+        let prev_frame = self.prev_reached_frame;
+        for cmd in commands.iter()
+        {
+            //Edge case: frame number goes back in time without a GAMESTART -> new game has happened
+            if cmd.game_frame < self.prev_reached_frame && cmd.event_name != "GAMESTART"
+            {
+                println!("Command goes back in time. Assuming new game has occured and resetting frame counter");
+                self.start_session(cmd.game_frame, HashMap::new(), false, &mut event_queue);
+                self.game_clock.observe_latest_write(newest_frame, write_instant);
+            }
+            if self.handle_session_command(cmd, &mut event_queue)
+            {
+                self.game_clock.observe_latest_write(newest_frame, write_instant);
+                continue;
+            }
+            self.prev_reached_frame = cmd.game_frame;
+            if !self.routing.accepts(cmd)
+            {
+                continue;
+            }
+            let command_age = self.game_clock.frame_age(cmd.game_frame, read_instant);
+            for mut bpevent in cmd.to_events(&self.macros, &self.game_clock)
+            {
+                if command_age > self.max_command_age && bpevent.is_transient()
+                {
+                    match self.stale_policy
+                    {
+                        BPStaleCommandPolicy::Keep => {},
+                        BPStaleCommandPolicy::Drop => {
+                            println!("Dropping command {:?}s old: {:?}", command_age.as_secs_f64(), cmd);
+                            continue;
+                        },
+                        BPStaleCommandPolicy::Compress => {
+                            if !bpevent.skip_time(command_age)
+                            {
+                                println!("Command {:?}s old has already finished: {:?}", command_age.as_secs_f64(), cmd);
+                                continue;
+                            }
+                        },
+                    }
+                }
+                event_queue.push_back(bpevent);
+            }
+        }
+        if self.prev_reached_frame != prev_frame
+        {
+            self.last_frame_progress_instant = Instant::now();
+            self.game_clock.observe_frame(self.prev_reached_frame, self.last_frame_progress_instant);
+            self.note_input();
+        }
+        if event_queue.len() != 0
+        {
+            println!("Total new events: {}", event_queue.len())
+        }
+        return event_queue;
+    }
+
+    //Handles commands that change the parser's own state rather than the simulator's.
+    //Returns true if the command was one of them.
+    fn handle_session_command(&mut self, cmd: &BPCommand, event_queue: &mut VecDeque<BPSimEvent>) -> bool
+    {
+        match cmd.event_name.as_str()
+        {
+            "GAMESTART" => {
+                let mut metadata: HashMap<String, String> = cmd.command_str_args.clone();
+                for (arg_name, arg_value) in cmd.command_args.iter()
+                {
+                    metadata.insert(arg_name.clone(), arg_value.to_string());
+                }
+                self.start_session(cmd.game_frame, metadata, true, event_queue);
+                true
+            },
+            "GAMEOVER" => {
+                println!("Game over at frame {}", cmd.game_frame);
+                self.session.active = false;
+                self.session.ended_at = Some(Instant::now());
+                self.prev_reached_frame = cmd.game_frame;
+                event_queue.push_back(BPSimEvent::new_stop_event());
+                true
+            },
+            "GAMESPEED" => {
+                self.game_clock.set_speed_override(cmd.command_args.get("Speed").copied().filter(|speed| *speed > 0.0));
+                true
+            },
+            _ => false,
+        }
+    }
+
+    //Starts a new game, stopping everything left over from the previous one
+    fn start_session(&mut self, first_frame: u64, metadata: HashMap<String, String>, explicit: bool, event_queue: &mut VecDeque<BPSimEvent>)
+    {
+        self.reset_state_for_new_game(first_frame);
+        self.session = BPSession::new(self.session.number + 1, first_frame, metadata, explicit);
+        println!("Started session {:?}", self.session);
+        event_queue.push_back(BPSimEvent::new_stop_event());
+    }
+
+    pub fn session(&self) -> &BPSession
+    {
+        &self.session
+    }
+
+    //Called by the input watchdog once the game has gone quiet
+    pub fn end_session_for_inactivity(&mut self)
+    {
+        if !self.session.active
+        {
+            return;
+        }
+        println!("No input from {} for a while, ending game {}", self.file_path.to_string_lossy(), self.session.number);
+        self.session.active = false;
+        self.session.timed_out = true;
+        self.session.ended_at = Some(Instant::now());
+    }
+
+    fn note_input(&mut self)
+    {
+        self.last_input_instant = Instant::now();
+        if self.session.timed_out
+        {
+            println!("Input from {} is back, resuming game {}", self.file_path.to_string_lossy(), self.session.number);
+            self.session.active = true;
+            self.session.timed_out = false;
+            self.session.ended_at = None;
+        }
+    }
+
+    pub fn time_since_input(&self) -> Duration
+    {
+        self.last_input_instant.elapsed()
+    }
+
+    pub fn debug_print_file(&mut self)
+    {
+        println!("Opening file {}", self.file_path.to_string_lossy());
+        let mut new_file = match File::open(self.file_path.as_path())
+        {
+            Ok(file) => file,
             Err(e) => {
                 match e.kind()
                 {
@@ -778,6 +2194,107 @@ impl BPDataParser
     //     }
     // }
 
+    //Rebuilds the events still running in the current game, for when the client starts mid-game.
+    //The current game is everything after the last RESET or the last time the frame number went backwards.
+    pub fn catch_up_current_game(&mut self) -> VecDeque<BPSimEvent>
+    {
+        let mut event_queue:VecDeque<BPSimEvent> = VecDeque::new();
+        let new_file = match File::open(self.file_path.as_path())
+        {
+            Ok(file) => file,
+            Err(e) => {
+                match e.kind()
+                {
+                    io::ErrorKind::NotFound =>
+                    {
+                        println!("ERROR: File {} not found", self.file_path.to_string_lossy());
+                    },
+                    io::ErrorKind::PermissionDenied =>
+                    {
+                        println!("ERROR: No permission to access {}", self.file_path.to_string_lossy());
+                    },
+                    _ =>
+                    {
+                        println!("ERROR: Some other unknown error: {}", e);
+                    }
+                }
+                return event_queue;
+            }
+        };
+        let read_instant = Instant::now();
+        let write_age = match new_file.metadata().and_then(|metadata| metadata.modified())
+        {
+            Err(_) => Duration::ZERO,
+            Ok(modified) => SystemTime::now().duration_since(modified).unwrap_or(Duration::ZERO),
+        };
+        //A log nobody wrote to in a while is most likely left over from a crashed game, don't bring its output back
+        if write_age > self.max_catch_up_age
+        {
+            println!("Not catching up, {} was last written {} s ago", self.file_path.to_string_lossy(), write_age.as_secs());
+            drop(new_file);
+            self.set_prev_event_to_latest();
+            return event_queue;
+        }
+        let write_instant = read_instant.checked_sub(write_age).unwrap_or(read_instant);
+        self.skip_to_end_of_file(&new_file);
+        //Collect the current game's commands, newest first
+        let mut game_commands: Vec<BPCommand> = Vec::new();
+        for line_res in RevLines::new(new_file) {
+            let line:String = match line_res {
+                Err(ref why) => {
+                    println!("Error when reading {}: {}", self.file_path.to_string_lossy(), why);
+                    return event_queue;
+                },
+                Ok(line_str) => line_str,
+            };
+            let cmd = match BPCommand::new(line) {
+                None => {
+                    println!("Could not parse command");
+                    continue;
+                },
+                Some(cmd) => cmd,
+            };
+            if let Some(later_cmd) = game_commands.last() {
+                if cmd.game_frame > later_cmd.game_frame {
+                    break;
+                }
+            }
+            let is_game_boundary = matches!(cmd.event_name.as_str(), "RESET" | "GAMESTART" | "GAMEOVER");
+            game_commands.push(cmd);
+            if is_game_boundary {
+                break;
+            }
+        }
+        let latest_frame = match game_commands.first() {
+            None => {
+                self.reset_state_for_new_game(0);
+                return event_queue;
+            },
+            Some(cmd) => cmd.game_frame,
+        };
+        self.reset_state_for_new_game(latest_frame);
+        self.game_clock.observe_latest_write(latest_frame, write_instant);
+        //Replay in order so CLEAR, CANCEL and keyed updates act on the events before them
+        for cmd in game_commands.iter().rev() {
+            if self.handle_session_command(cmd, &mut event_queue) {
+                self.game_clock.observe_latest_write(latest_frame, write_instant);
+                continue;
+            }
+            if !self.routing.accepts(cmd) {
+                continue;
+            }
+            let command_age = self.game_clock.frame_age(cmd.game_frame, read_instant);
+            for mut bpevent in cmd.to_events(&self.macros, &self.game_clock) {
+                if bpevent.skip_time(command_age) {
+                    event_queue.push_back(bpevent);
+                }
+            }
+        }
+        self.prev_reached_frame = latest_frame;
+        println!("Caught up on {} commands, {} events still running", game_commands.len(), event_queue.len());
+        event_queue
+    }
+
     pub fn set_prev_event_to_latest(&mut self)
     {
         let new_file = match File::open(self.file_path.as_path())
@@ -802,6 +2319,7 @@ impl BPDataParser
                 return;
             }
         };
+        self.skip_to_end_of_file(&new_file);
         let mut rev_lines = RevLines::new(new_file);
         let mut latest_frame = self.prev_reached_frame;
         match rev_lines.next()
@@ -835,22 +2353,1212 @@ impl BPDataParser
         self.reset_state_for_new_game(latest_frame);
     }
 
-    fn reset_state_for_new_game(&mut self, new_first_frame: u64)
+    pub fn game_speed(&self) -> f64
+    {
+        self.game_clock.game_speed()
+    }
+
+    pub fn input_lag(&self) -> Duration
+    {
+        self.input_lag
+    }
+
+    pub fn time_since_frame_progress(&self) -> Duration
+    {
+        self.last_frame_progress_instant.elapsed()
+    }
+
+    fn reset_state_for_new_game(&mut self, new_first_frame: u64)
     {   
         if self.prev_reached_frame > new_first_frame {
             println!("Warning: New first frame is later than the previous last frame.");
         }
-        self.prev_reached_frame = new_first_frame;
-        self.first_read = true;
-        return
+        self.prev_reached_frame = new_first_frame;
+        self.game_clock.reset();
+        return
+    }
+
+    //Marks everything currently in the file as already read
+    fn skip_to_end_of_file(&mut self, file: &File)
+    {
+        match file.metadata()
+        {
+            Err(why) => println!("Error when reading {}: {}", self.file_path.to_string_lossy(), why),
+            Ok(metadata) => {
+                self.read_offset = metadata.len();
+                self.file_created = metadata.created().ok();
+            }
+        }
+    }
+}
+//One command log feeding the simulator. Several sources can drive the same devices,
+//each with its own frame tracking, on/off switch and strength weight.
+pub struct BPCommandSource
+{
+    pub parser: BPDataParser,
+    pub name: String,
+    pub enabled: bool,
+    //Multiplies the strength of every event from this source
+    pub weight: f64,
+    //Added to every event, so this source can stop its own events without touching the others
+    tag: String,
+}
+
+impl BPCommandSource
+{
+    pub fn new(file_address: String) -> BPCommandSource
+    {
+        let mut parser = BPDataParser::new(file_address.clone());
+        parser.set_macros(BPMacroTable::load(MACRO_FILE_PATH));
+        BPCommandSource
+        {
+            parser,
+            tag: format!("source:{}", file_address),
+            name: file_address,
+            enabled: true,
+            weight: 1.0,
+        }
+    }
+
+    //When the simulator is shared with other sources, a RESET or new game only cancels this source's events
+    pub fn get_new_events(&mut self, shared_sim: bool) -> VecDeque<BPSimEvent>
+    {
+        //Always read so the frame tracking stays current while disabled
+        let event_queue = self.parser.get_new_events();
+        self.apply_source_settings(event_queue, shared_sim)
+    }
+
+    pub fn catch_up_current_game(&mut self, shared_sim: bool) -> VecDeque<BPSimEvent>
+    {
+        let event_queue = self.parser.catch_up_current_game();
+        self.apply_source_settings(event_queue, shared_sim)
+    }
+
+    //Ends everything this source added to the simulator, for when it's turned off or removed
+    pub fn cancel_event(&self) -> BPSimEvent
+    {
+        BPSimEvent::new(Duration::ZERO, BPActionType::Cancel { filter: BPCancelFilter::Tag(self.tag.clone()) })
+    }
+
+    //Keeps commands that reach into existing events from touching other sources' events in a shared simulator
+    fn scope_to_source(&self, bpevent: &mut BPSimEvent)
+    {
+        let scoped = |filter: BPCancelFilter| BPActionType::Cancel { filter: BPCancelFilter::Scoped { tag: self.tag.clone(), filter: Box::new(filter) } };
+        match bpevent.action
+        {
+            BPActionType::Stop => {
+                bpevent.action = BPActionType::Cancel { filter: BPCancelFilter::Tag(self.tag.clone()) };
+            }
+            //A zero POWER ends every POWER event, so only end this source's
+            BPActionType::Power { strength, .. } if strength == 0.0 => {
+                bpevent.action = scoped(BPCancelFilter::Type("POWER".to_string()));
+            }
+            BPActionType::Set { ref mut key, .. } | BPActionType::Clear { ref mut key } => {
+                *key = format!("{}/{}", self.tag, key);
+            }
+            BPActionType::Cancel { ref filter } => {
+                bpevent.action = scoped(filter.clone());
+            }
+            _ => {}
+        }
+    }
+
+    fn apply_source_settings(&self, mut event_queue: VecDeque<BPSimEvent>, shared_sim: bool) -> VecDeque<BPSimEvent>
+    {
+        if !self.enabled
+        {
+            //A panic stop always gets through
+            event_queue.retain(|bpevent| matches!(bpevent.action, BPActionType::Panic));
+            return event_queue;
+        }
+        for bpevent in event_queue.iter_mut()
+        {
+            if shared_sim
+            {
+                self.scope_to_source(bpevent);
+            }
+            bpevent.scale_strength(self.weight);
+            bpevent.tags.push(self.tag.clone());
+        }
+        event_queue
+    }
+
+    pub fn show_ui(&mut self, ui: &mut egui::Ui)
+    {
+        ui.checkbox(&mut self.enabled, "Enabled");
+        ui.add(egui::Slider::new(&mut self.weight, 0.0..=2.0).text("Strength weight"));
+        let session = self.parser.session();
+        if session.active {
+            ui.label(format!("Game {} running since frame {}", session.number, session.start_frame));
+        }
+        else if session.timed_out {
+            ui.label(format!("Game {} went silent", session.number));
+        }
+        else {
+            ui.label(format!("Game {} over", session.number));
+        }
+        ui.label(format!("Game speed: {:.2}x, input lag: {} ms", self.parser.game_speed(), self.parser.input_lag().as_millis()));
+        egui::ComboBox::from_id_source(format!("stale_policy_{}", self.name))
+            .selected_text(format!("{:?}", self.parser.stale_policy))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.parser.stale_policy, BPStaleCommandPolicy::Keep, "Keep");
+                ui.selectable_value(&mut self.parser.stale_policy, BPStaleCommandPolicy::Drop, "Drop");
+                ui.selectable_value(&mut self.parser.stale_policy, BPStaleCommandPolicy::Compress, "Compress");
+            });
+        let mut max_command_age_millis = self.parser.max_command_age.as_millis() as u64;
+        if ui.add(egui::Slider::new(&mut max_command_age_millis, 100..=5000).text("Max command age (millis)")).changed() {
+            self.parser.max_command_age = Duration::from_millis(max_command_age_millis);
+        }
+        ui.horizontal(|ui| {
+            ui.label("Player ID:");
+            ui.add(egui::TextEdit::singleline(&mut self.parser.routing.player_id).desired_width(40.0));
+            ui.label("Team ID:");
+            ui.add(egui::TextEdit::singleline(&mut self.parser.routing.team_id).desired_width(40.0));
+        });
+        ui.checkbox(&mut self.parser.routing.receive_team_events, "Also receive team events");
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BPWatchdogEvent
+{
+    //Input has been idle for too long, output starts fading
+    Tripped,
+    //Output has fully faded, time to drop everything still running
+    FadedOut,
+    //Input is back after tripping
+    Resumed,
+}
+
+//Fades output to zero when the game stops writing commands, for when the game or widget dies with events still running
+#[derive(Debug)]
+pub struct BPInputWatchdog
+{
+    pub enabled: bool,
+    pub idle_timeout: Duration,
+    pub fade_time: Duration,
+    tripped_at: Option<Instant>,
+    faded_out: bool,
+    //Last time idling was expected, idle time only counts from here
+    held_at: Option<Instant>,
+}
+
+impl Default for BPInputWatchdog
+{
+    fn default() -> BPInputWatchdog
+    {
+        BPInputWatchdog {
+            enabled: true,
+            idle_timeout: Duration::from_secs(60),
+            fade_time: Duration::from_secs(3),
+            tripped_at: None,
+            faded_out: false,
+            held_at: None,
+        }
+    }
+}
+
+impl BPInputWatchdog
+{
+    //Checks how long input has been idle for, returns what changed since the last check
+    pub fn update(&mut self, idle_for: Duration, now: Instant) -> Option<BPWatchdogEvent>
+    {
+        let idle_for = match self.held_at
+        {
+            None => idle_for,
+            Some(held_at) => idle_for.min(now.saturating_duration_since(held_at)),
+        };
+        if !self.enabled || idle_for < self.idle_timeout
+        {
+            return match self.tripped_at.take()
+            {
+                None => None,
+                Some(_) => {
+                    self.faded_out = false;
+                    Some(BPWatchdogEvent::Resumed)
+                }
+            };
+        }
+        match self.tripped_at
+        {
+            None => {
+                self.tripped_at = Some(now);
+                Some(BPWatchdogEvent::Tripped)
+            }
+            Some(tripped_at) => {
+                if !self.faded_out && now.saturating_duration_since(tripped_at) >= self.fade_time
+                {
+                    self.faded_out = true;
+                    return Some(BPWatchdogEvent::FadedOut);
+                }
+                None
+            }
+        }
+    }
+
+    //Call instead of update while the game is expected to be quiet, like when it paused the simulator
+    pub fn hold(&mut self, now: Instant)
+    {
+        self.held_at = Some(now);
+        self.tripped_at = None;
+        self.faded_out = false;
+    }
+
+    pub fn is_tripped(&self) -> bool
+    {
+        self.tripped_at.is_some()
+    }
+
+    //Multiplier for the output, falls from 1 to 0 over the fade time once tripped
+    pub fn output_scale(&self, now: Instant) -> f64
+    {
+        match self.tripped_at
+        {
+            None => 1.0,
+            Some(tripped_at) => {
+                if self.fade_time.is_zero()
+                {
+                    return 0.0;
+                }
+                let faded = now.saturating_duration_since(tripped_at).as_secs_f64() / self.fade_time.as_secs_f64();
+                f64::max(1.0 - faded, 0.0)
+            }
+        }
+    }
+
+    pub fn show_ui(&mut self, ui: &mut egui::Ui, idle_for: Duration)
+    {
+        ui.checkbox(&mut self.enabled, "Stop when the game goes silent");
+        let mut idle_timeout_secs = self.idle_timeout.as_secs();
+        if ui.add(egui::Slider::new(&mut idle_timeout_secs, 5..=600).text("Idle timeout (seconds)")).changed()
+        {
+            self.idle_timeout = Duration::from_secs(idle_timeout_secs);
+        }
+        let mut fade_millis = self.fade_time.as_millis() as u64;
+        if ui.add(egui::Slider::new(&mut fade_millis, 0..=10000).text("Fade out (millis)")).changed()
+        {
+            self.fade_time = Duration::from_millis(fade_millis);
+        }
+        if self.is_tripped()
+        {
+            ui.label(format!("No input for {} seconds, output stopped", idle_for.as_secs()));
+        }
+        else
+        {
+            ui.label(format!("Last input {} seconds ago", idle_for.as_secs()));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BPWellnessState
+{
+    Running,
+    Break { ends_at: Instant },
+    //Time is up for this session, nothing plays until a new one is started
+    Ended,
+}
+
+//Session limits for long play: regular cool-down breaks, a cap on total stimulation and a hard session end.
+//Sits in the output pipeline and mutes everything outside of Running.
+#[derive(Debug)]
+pub struct BPWellnessTimer
+{
+    pub enabled: bool,
+    //Total stimulation allowed in one session
+    pub max_active_time: Duration,
+    //Stimulation allowed before a break is enforced
+    pub break_every: Duration,
+    pub break_length: Duration,
+    //Wall time after which the session ends no matter what
+    pub session_length: Duration,
+    //Print game events that are dropped during breaks
+    pub log_break_events: bool,
+    state: BPWellnessState,
+    session_started_at: Option<Instant>,
+    active_time: Duration,
+    active_since_break: Duration,
+    was_active: bool,
+    last_apply_instant: Option<Instant>,
+    dropped_event_count: usize,
+}
+
+impl Default for BPWellnessTimer
+{
+    fn default() -> BPWellnessTimer
+    {
+        BPWellnessTimer {
+            enabled: false,
+            max_active_time: Duration::from_secs(60 * 60),
+            break_every: Duration::from_secs(20 * 60),
+            break_length: Duration::from_secs(5 * 60),
+            session_length: Duration::from_secs(3 * 60 * 60),
+            log_break_events: true,
+            state: BPWellnessState::Running,
+            session_started_at: None,
+            active_time: Duration::ZERO,
+            active_since_break: Duration::ZERO,
+            was_active: false,
+            last_apply_instant: None,
+            dropped_event_count: 0,
+        }
+    }
+}
+
+impl BPWellnessTimer
+{
+    //Below this the output doesn't count as stimulation
+    const ON_THRESHOLD: f64 = 0.01;
+
+    pub fn start_new_session(&mut self)
+    {
+        println!("Starting a new wellness session");
+        self.state = BPWellnessState::Running;
+        self.session_started_at = None;
+        self.active_time = Duration::ZERO;
+        self.active_since_break = Duration::ZERO;
+        self.was_active = false;
+        self.last_apply_instant = None;
+        self.dropped_event_count = 0;
+    }
+
+    pub fn state(&self) -> BPWellnessState
+    {
+        self.state
+    }
+
+    //Mutes the output outside of Running, and counts stimulation time towards the limits
+    pub fn apply(&mut self, intensities: Vec<f64>, now: Instant) -> Vec<f64>
+    {
+        if !self.enabled
+        {
+            self.was_active = false;
+            self.last_apply_instant = Some(now);
+            return intensities;
+        }
+        let session_started_at = *self.session_started_at.get_or_insert(now);
+        //The previous output was held until now
+        if let Some(last_instant) = self.last_apply_instant
+        {
+            if self.was_active
+            {
+                let active_for = now.saturating_duration_since(last_instant);
+                self.active_time += active_for;
+                self.active_since_break += active_for;
+            }
+        }
+        self.last_apply_instant = Some(now);
+        if self.state != BPWellnessState::Ended
+            && (now.saturating_duration_since(session_started_at) >= self.session_length || self.active_time >= self.max_active_time)
+        {
+            println!("Wellness session over after {:?} of stimulation", self.active_time);
+            self.state = BPWellnessState::Ended;
+        }
+        if let BPWellnessState::Break { ends_at } = self.state
+        {
+            if now >= ends_at
+            {
+                println!("Break over");
+                self.state = BPWellnessState::Running;
+                self.active_since_break = Duration::ZERO;
+            }
+        }
+        if self.state == BPWellnessState::Running && self.active_since_break >= self.break_every
+        {
+            println!("Taking a {:?} break", self.break_length);
+            self.state = BPWellnessState::Break { ends_at: now + self.break_length };
+        }
+        if self.state != BPWellnessState::Running
+        {
+            self.was_active = false;
+            return vec![0.0; intensities.len()];
+        }
+        self.was_active = intensities.iter().any(|intensity| *intensity >= BPWellnessTimer::ON_THRESHOLD);
+        intensities
+    }
+
+    //Drops events that would start output during a break or after the session has ended.
+    //Updates to keys already in the simulator still go through, so they end up where the game expects once the break is over.
+    fn filter_events(&mut self, mut event_queue: VecDeque<BPSimEvent>, bp_sim: &BPSimulator) -> VecDeque<BPSimEvent>
+    {
+        if !self.enabled || self.state == BPWellnessState::Running
+        {
+            return event_queue;
+        }
+        let log_break_events = self.log_break_events;
+        let mut dropped_count = 0;
+        event_queue.retain(|bpevent| {
+            if !bpevent.starts_output()
+            {
+                return true;
+            }
+            if let BPActionType::Set { ref key, .. } = bpevent.action
+            {
+                if bp_sim.has_keyed_event(key)
+                {
+                    return true;
+                }
+            }
+            if log_break_events
+            {
+                println!("Dropped during break: {:?}", bpevent);
+            }
+            dropped_count += 1;
+            false
+        });
+        self.dropped_event_count += dropped_count;
+        event_queue
+    }
+
+    fn format_countdown(duration: Duration) -> String
+    {
+        format!("{}:{:02}", duration.as_secs() / 60, duration.as_secs() % 60)
+    }
+
+    pub fn show_ui(&mut self, ui: &mut egui::Ui)
+    {
+        ui.checkbox(&mut self.enabled, "Enable session timers");
+        let mut max_active_mins = self.max_active_time.as_secs() / 60;
+        if ui.add(egui::Slider::new(&mut max_active_mins, 1..=240).text("Max stimulation per session (minutes)")).changed()
+        {
+            self.max_active_time = Duration::from_secs(max_active_mins * 60);
+        }
+        let mut break_every_mins = self.break_every.as_secs() / 60;
+        if ui.add(egui::Slider::new(&mut break_every_mins, 1..=120).text("Break after stimulation (minutes)")).changed()
+        {
+            self.break_every = Duration::from_secs(break_every_mins * 60);
+        }
+        let mut break_length_mins = self.break_length.as_secs() / 60;
+        if ui.add(egui::Slider::new(&mut break_length_mins, 1..=60).text("Break length (minutes)")).changed()
+        {
+            self.break_length = Duration::from_secs(break_length_mins * 60);
+        }
+        let mut session_length_mins = self.session_length.as_secs() / 60;
+        if ui.add(egui::Slider::new(&mut session_length_mins, 10..=720).text("Hard session end (minutes)")).changed()
+        {
+            self.session_length = Duration::from_secs(session_length_mins * 60);
+        }
+        ui.checkbox(&mut self.log_break_events, "Log events dropped during breaks");
+        if !self.enabled
+        {
+            return;
+        }
+        let now = Instant::now();
+        match self.state
+        {
+            BPWellnessState::Running => {
+                ui.label(format!("Next break in {} of stimulation", BPWellnessTimer::format_countdown(self.break_every.saturating_sub(self.active_since_break))));
+                ui.label(format!("Stimulation left this session: {}", BPWellnessTimer::format_countdown(self.max_active_time.saturating_sub(self.active_time))));
+            },
+            BPWellnessState::Break { ends_at } => {
+                ui.label(format!("On a break, {} left", BPWellnessTimer::format_countdown(ends_at.saturating_duration_since(now))));
+            },
+            BPWellnessState::Ended => {
+                ui.label("Session over");
+            },
+        }
+        if let Some(session_started_at) = self.session_started_at
+        {
+            if self.state != BPWellnessState::Ended
+            {
+                let session_left = self.session_length.saturating_sub(now.saturating_duration_since(session_started_at));
+                ui.label(format!("Session ends in {}", BPWellnessTimer::format_countdown(session_left)));
+            }
+        }
+        if self.dropped_event_count > 0
+        {
+            ui.label(format!("Events dropped during breaks: {}", self.dropped_event_count));
+        }
+        if ui.button("Start New Session").clicked()
+        {
+            self.start_new_session();
+        }
+    }
+}
+
+//Last stage before the device, keeps whatever the simulator asks for within the user's limits
+#[derive(Debug)]
+pub struct BPSafetyLimiter
+{
+    pub global_max: f64,
+    //Per motor caps, motors without one are only held to the global max
+    pub motor_max: HashMap<usize, f64>,
+    //Force a motor off for rest_time after it has been on for max_on_time without a break
+    pub limit_on_time: bool,
+    pub max_on_time: Duration,
+    pub rest_time: Duration,
+    //Scale output down once more than energy_budget full strength seconds were used within energy_window
+    pub limit_energy: bool,
+    pub energy_window: Duration,
+    pub energy_budget: f64,
+    on_since: HashMap<usize, Instant>,
+    resting_until: HashMap<usize, Instant>,
+    //Energy sent per motor, oldest first
+    energy_samples: VecDeque<(Instant, f64)>,
+    last_output: Vec<f64>,
+    last_apply_instant: Option<Instant>,
+}
+
+impl Default for BPSafetyLimiter
+{
+    fn default() -> BPSafetyLimiter
+    {
+        BPSafetyLimiter {
+            global_max: 1.0,
+            motor_max: HashMap::new(),
+            limit_on_time: false,
+            max_on_time: Duration::from_secs(600),
+            rest_time: Duration::from_secs(30),
+            limit_energy: false,
+            energy_window: Duration::from_secs(300),
+            energy_budget: 120.0,
+            on_since: HashMap::new(),
+            resting_until: HashMap::new(),
+            energy_samples: VecDeque::new(),
+            last_output: Vec::new(),
+            last_apply_instant: None,
+        }
+    }
+}
+
+impl BPSafetyLimiter
+{
+    //Below this a motor counts as off
+    const ON_THRESHOLD: f64 = 0.01;
+
+    pub fn motor_max(&self, motor: usize) -> f64
+    {
+        f64::min(self.global_max, *self.motor_max.get(&motor).unwrap_or(&1.0))
+    }
+
+    //Full strength seconds sent within the energy window
+    pub fn energy_used(&self) -> f64
+    {
+        self.energy_samples.iter().map(|(_, energy)| energy).sum()
+    }
+
+    pub fn is_resting(&self, motor: usize, now: Instant) -> bool
+    {
+        self.resting_until.get(&motor).is_some_and(|until| now < *until)
+    }
+
+    //Limits the intensities about to be sent to the device at time now
+    pub fn apply(&mut self, intensities: Vec<f64>, now: Instant) -> Vec<f64>
+    {
+        //The previous output was held until now, count it towards the budget
+        if let Some(last_instant) = self.last_apply_instant
+        {
+            let held_for = now.saturating_duration_since(last_instant).as_secs_f64();
+            let energy: f64 = self.last_output.iter().map(|intensity| intensity * held_for).sum();
+            if energy > 0.0
+            {
+                self.energy_samples.push_back((now, energy));
+            }
+        }
+        while self.energy_samples.front().is_some_and(|(instant, _)| now.saturating_duration_since(*instant) > self.energy_window)
+        {
+            self.energy_samples.pop_front();
+        }
+        let energy_scale = if self.limit_energy && self.energy_used() > self.energy_budget
+        {
+            self.energy_budget / self.energy_used()
+        }
+        else
+        {
+            1.0
+        };
+        let mut output: Vec<f64> = Vec::new();
+        for (motor, intensity) in intensities.iter().enumerate()
+        {
+            let mut limited = f64::min(f64::max(*intensity, 0.0), self.motor_max(motor)) * energy_scale;
+            if self.limit_on_time
+            {
+                if self.is_resting(motor, now)
+                {
+                    limited = 0.0;
+                }
+                else if limited < BPSafetyLimiter::ON_THRESHOLD
+                {
+                    self.on_since.remove(&motor);
+                }
+                else
+                {
+                    let on_since = *self.on_since.entry(motor).or_insert(now);
+                    if now.saturating_duration_since(on_since) >= self.max_on_time
+                    {
+                        println!("Motor {} has been on for {:?}, resting it for {:?}", motor, self.max_on_time, self.rest_time);
+                        self.resting_until.insert(motor, now + self.rest_time);
+                        self.on_since.remove(&motor);
+                        limited = 0.0;
+                    }
+                }
+            }
+            output.push(limited);
+        }
+        self.last_output.clone_from(&output);
+        self.last_apply_instant = Some(now);
+        output
+    }
+
+    pub fn show_ui(&mut self, ui: &mut egui::Ui, motor_count: usize)
+    {
+        ui.add(egui::Slider::new(&mut self.global_max, 0.0..=1.0).text("Global max strength"));
+        for motor in 0..motor_count
+        {
+            let mut max = *self.motor_max.get(&motor).unwrap_or(&1.0);
+            if ui.add(egui::Slider::new(&mut max, 0.0..=1.0).text(format!("Motor {} max strength", motor))).changed()
+            {
+                self.motor_max.insert(motor, max);
+            }
+        }
+        ui.checkbox(&mut self.limit_on_time, "Limit continuous on-time");
+        let mut max_on_time_secs = self.max_on_time.as_secs();
+        if ui.add(egui::Slider::new(&mut max_on_time_secs, 10..=3600).text("Max on-time (seconds)")).changed()
+        {
+            self.max_on_time = Duration::from_secs(max_on_time_secs);
+        }
+        let mut rest_time_secs = self.rest_time.as_secs();
+        if ui.add(egui::Slider::new(&mut rest_time_secs, 1..=600).text("Rest time (seconds)")).changed()
+        {
+            self.rest_time = Duration::from_secs(rest_time_secs);
+        }
+        ui.checkbox(&mut self.limit_energy, "Limit energy");
+        let mut energy_window_secs = self.energy_window.as_secs();
+        if ui.add(egui::Slider::new(&mut energy_window_secs, 10..=3600).text("Energy window (seconds)")).changed()
+        {
+            self.energy_window = Duration::from_secs(energy_window_secs);
+        }
+        ui.add(egui::Slider::new(&mut self.energy_budget, 1.0..=3600.0).text("Energy budget (full strength seconds)"));
+        ui.label(format!("Energy used: {:.1} of {:.1}", self.energy_used(), self.energy_budget));
+        let now = Instant::now();
+        let resting: Vec<usize> = (0..motor_count).filter(|motor| self.is_resting(*motor, now)).collect();
+        if !resting.is_empty()
+        {
+            ui.label(format!("Resting motors: {:?}", resting));
+        }
+    }
+}
+
+//Bluetooth only takes roughly 6-10 writes a second, so only send when the device would actually feel a difference
+#[derive(Debug)]
+pub struct BPOutputQuantizer
+{
+    pub enabled: bool,
+    //Resend the current strengths this often even when unchanged, in case a write got lost
+    pub keep_alive: Duration,
+    //What each device was last sent and when
+    last_sent: HashMap<usize, (Vec<f64>, Instant)>,
+    pub writes_sent: u64,
+    pub writes_skipped: u64,
+}
+
+impl Default for BPOutputQuantizer
+{
+    fn default() -> BPOutputQuantizer
+    {
+        BPOutputQuantizer {
+            enabled: true,
+            keep_alive: Duration::from_secs(2),
+            last_sent: HashMap::new(),
+            writes_sent: 0,
+            writes_skipped: 0,
+        }
+    }
+}
+
+impl BPOutputQuantizer
+{
+    //Rounds each strength down to a step its motor can do, so output after the safety limiter never ends up above its caps.
+    //Buttplug turns a scalar back into a step with ceil(scalar * steps), so step k is sent as the middle of (k-1, k],
+    //which lands on k however the multiplication rounds. Motors without a known step count are left alone
+    pub fn quantize(strengths: &[f64], steps: &[u32]) -> Vec<f64>
+    {
+        strengths.iter().enumerate().map(|(motor, strength)| {
+            match steps.get(motor)
+            {
+                Some(&step_count) if step_count > 0 => {
+                    let step = (strength * step_count as f64).floor();
+                    if step > 0.0 { (step - 0.5) / step_count as f64 } else { 0.0 }
+                },
+                _ => *strength,
+            }
+        }).collect()
+    }
+
+    //The strengths to write to a device now, or None when it already has them and no keep-alive is due
+    pub fn prepare(&mut self, device: usize, strengths: &[f64], steps: &[u32], now: Instant) -> Option<Vec<f64>>
+    {
+        let strengths = if self.enabled { BPOutputQuantizer::quantize(strengths, steps) } else { strengths.to_vec() };
+        if let Some((last_strengths, last_instant)) = self.last_sent.get(&device)
+        {
+            if self.enabled && now - *last_instant < self.keep_alive && *last_strengths == strengths
+            {
+                self.writes_skipped += 1;
+                return None;
+            }
+        }
+        self.last_sent.insert(device, (strengths.clone(), now));
+        self.writes_sent += 1;
+        Some(strengths)
+    }
+
+    //Forget what was sent, so the next strengths go out no matter what
+    pub fn reset(&mut self)
+    {
+        self.last_sent.clear();
+    }
+
+    pub fn show_ui(&mut self, ui: &mut egui::Ui)
+    {
+        ui.checkbox(&mut self.enabled, "Quantize to device steps and skip unchanged writes");
+        let mut keep_alive_secs = self.keep_alive.as_secs_f64();
+        if ui.add(egui::Slider::new(&mut keep_alive_secs, 0.2..=10.0).text("Keep-alive resend (seconds)")).changed()
+        {
+            self.keep_alive = Duration::from_secs_f64(keep_alive_secs);
+        }
+        ui.label(format!("Writes sent: {}, skipped: {}", self.writes_sent, self.writes_skipped));
+    }
+}
+
+//Splits the simulator's motors into one slice per device of the layout they were set up for, leaving out devices without vibrators
+pub fn split_motors_by_device<'a>(intensities: &[f64], device_layout: &'a [BPDeviceInfo]) -> Vec<(&'a BPDeviceInfo, Vec<f64>)>
+{
+    let mut first_motor = 0;
+    device_layout.iter().filter(|device| device.vibrator_count > 0).map(|device| {
+        let motors = intensities.iter().skip(first_motor).take(device.vibrator_count).copied().collect();
+        first_motor += device.vibrator_count;
+        (device, motors)
+    }).collect()
+}
+
+//How much each new latency measurement moves a device's average
+const OUTPUT_LATENCY_SMOOTHING: f64 = 0.3;
+//Writes older than this no longer count toward the achieved rate
+const OUTPUT_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+//What the scheduler knows about one device
+#[derive(Debug, Default)]
+struct BPDeviceRate
+{
+    //Smoothed time a write to the device takes
+    latency: Option<Duration>,
+    last_order_instant: Option<Instant>,
+    recent_writes: VecDeque<Instant>,
+}
+
+//Decides when each device gets its next write, slowing down devices whose writes take long
+//Output is computed fresh whenever a device is due, so a slow device skips to the latest value instead of queueing stale ones
+#[derive(Debug)]
+pub struct BPOutputScheduler
+{
+    pub adaptive: bool,
+    //Fastest a device is ever written to, and the fixed period when not adaptive
+    pub min_period: Duration,
+    pub max_period: Duration,
+    //Period as a multiple of the measured latency, so the device has time to settle between writes
+    pub latency_headroom: f64,
+    devices: HashMap<usize, BPDeviceRate>,
+}
+
+impl Default for BPOutputScheduler
+{
+    fn default() -> BPOutputScheduler
+    {
+        BPOutputScheduler {
+            adaptive: true,
+            min_period: Duration::from_millis(100),
+            max_period: Duration::from_millis(1000),
+            latency_headroom: 2.0,
+            devices: HashMap::new(),
+        }
+    }
+}
+
+impl BPOutputScheduler
+{
+    pub fn period(&self, device: usize) -> Duration
+    {
+        let latency = self.devices.get(&device).and_then(|rate| rate.latency);
+        match latency
+        {
+            Some(latency) if self.adaptive => latency.mul_f64(self.latency_headroom).clamp(self.min_period, self.max_period.max(self.min_period)),
+            _ => self.min_period,
+        }
+    }
+
+    pub fn is_due(&self, device: usize, now: Instant) -> bool
+    {
+        match self.devices.get(&device).and_then(|rate| rate.last_order_instant)
+        {
+            None => true,
+            Some(last_order_instant) => now - last_order_instant >= self.period(device),
+        }
+    }
+
+    //Call whenever a due device was handled, latency is how long the write took or None when nothing had to be written
+    pub fn record(&mut self, device: usize, now: Instant, latency: Option<Duration>)
+    {
+        let rate = self.devices.entry(device).or_default();
+        rate.last_order_instant = Some(now);
+        if let Some(latency) = latency
+        {
+            rate.latency = Some(match rate.latency
+            {
+                None => latency,
+                Some(average) => average.mul_f64(1.0 - OUTPUT_LATENCY_SMOOTHING) + latency.mul_f64(OUTPUT_LATENCY_SMOOTHING),
+            });
+            rate.recent_writes.push_back(now);
+        }
+        while rate.recent_writes.front().is_some_and(|write| now - *write > OUTPUT_RATE_WINDOW)
+        {
+            rate.recent_writes.pop_front();
+        }
+    }
+
+    //Writes per second the device actually got recently
+    pub fn achieved_rate(&self, device: usize, now: Instant) -> f64
+    {
+        let recent = self.devices.get(&device).map_or(0, |rate| rate.recent_writes.iter().filter(|write| now - **write <= OUTPUT_RATE_WINDOW).count());
+        recent as f64 / OUTPUT_RATE_WINDOW.as_secs_f64()
+    }
+
+    //Forget all measurements, for when the devices change
+    pub fn reset(&mut self)
+    {
+        self.devices.clear();
+    }
+
+    pub fn show_ui(&mut self, ui: &mut egui::Ui)
+    {
+        ui.checkbox(&mut self.adaptive, "Adapt write rate to device latency");
+        let mut min_period_millis = self.min_period.as_millis() as u64;
+        if ui.add(egui::Slider::new(&mut min_period_millis, 20..=1000).text("Min write period (ms)")).changed()
+        {
+            self.min_period = Duration::from_millis(min_period_millis);
+        }
+        let mut max_period_millis = self.max_period.as_millis() as u64;
+        if ui.add(egui::Slider::new(&mut max_period_millis, 100..=5000).text("Max write period (ms)")).changed()
+        {
+            self.max_period = Duration::from_millis(max_period_millis);
+        }
+        ui.add(egui::Slider::new(&mut self.latency_headroom, 1.0..=5.0).text("Latency headroom"));
+        let now = Instant::now();
+        let mut devices: Vec<usize> = self.devices.keys().copied().collect();
+        devices.sort();
+        for device in devices
+        {
+            let latency = self.devices[&device].latency.unwrap_or_default();
+            ui.label(format!("Device {}: latency {} ms, period {} ms, {:.1} writes/s",
+                device, latency.as_millis(), self.period(device).as_millis(), self.achieved_rate(device, now)));
+        }
+    }
+}
+
+const CRASH_REPORT_PATH: &str = "bab_crash_report.txt";
+const DRY_RUN_LOG_PATH: &str = "bab_dry_run_log.txt";
+
+//Record of a dry run: every event that reached the simulator and every output that would have been sent
+pub struct BPDryRunLog
+{
+    file: File,
+    started_at: Instant,
+}
+
+impl BPDryRunLog
+{
+    pub fn create(path: &str) -> io::Result<BPDryRunLog>
+    {
+        Ok(BPDryRunLog {
+            file: File::create(path)?,
+            started_at: Instant::now(),
+        })
+    }
+
+    pub fn log_events(&mut self, event_queue: &VecDeque<BPSimEvent>)
+    {
+        for bpevent in event_queue.iter()
+        {
+            self.write_line(&format!("EVENT {:?}", bpevent));
+        }
+    }
+
+    pub fn log_output(&mut self, intensities: &[f64])
+    {
+        self.write_line(&format!("OUTPUT {:?}", intensities));
+    }
+
+    fn write_line(&mut self, line: &str)
+    {
+        //Milliseconds since the dry run started, so runs can be compared
+        if let Err(err) = writeln!(self.file, "{} {}", self.started_at.elapsed().as_millis(), line)
+        {
+            println!("Could not write to dry run log: {err}");
+        }
+    }
+}
+
+//The connected client, shared so the panic hook can still stop devices when the app itself is unwinding
+static DEVICE_STOP_CLIENT: std::sync::Mutex<Option<std::sync::Arc<ButtplugClient>>> = std::sync::Mutex::new(None);
+
+//On a panic, writes a crash report and stops every device before the default panic output
+fn install_crash_handler() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        handle_crash(Path::new(CRASH_REPORT_PATH), panic_info);
+        default_hook(panic_info);
+    }));
+}
+
+//Everything the panic hook does besides the default output
+fn handle_crash(report_path: &Path, panic_description: &dyn std::fmt::Display) {
+    match write_crash_report(report_path, panic_description) {
+        Err(err) => println!("Could not write crash report: {err}"),
+        Ok(_) => println!("Crash report written to {}", report_path.to_string_lossy()),
+    }
+    stop_devices_from_any_thread();
+}
+
+fn write_crash_report(report_path: &Path, panic_description: &dyn std::fmt::Display) -> io::Result<()> {
+    let mut report = std::fs::OpenOptions::new().create(true).append(true).open(report_path)?;
+    let crash_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    writeln!(report, "Crash at {} seconds since the Unix epoch", crash_time.as_secs())?;
+    writeln!(report, "{panic_description}")?;
+    writeln!(report, "{}\n", std::backtrace::Backtrace::force_capture())?;
+    Ok(())
+}
+
+//Stops devices on a fresh thread, so it works even if this thread is already inside a runtime
+fn stop_devices_from_any_thread() {
+    let client = match DEVICE_STOP_CLIENT.lock() {
+        Err(poisoned) => poisoned.into_inner().clone(),
+        Ok(client) => client.clone(),
+    };
+    let client = match client {
+        None => return,
+        Some(client) => client,
+    };
+    let stopper = std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        rt.block_on(async {
+            time::timeout(Duration::from_secs(2), client.stop_all_devices()).await
+        }).map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "stopping devices timed out"))?
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("{err:?}")))
+    });
+    match stopper.join() {
+        Ok(Ok(())) => println!("Stopped all devices"),
+        Ok(Err(err)) => println!("Failed to stop devices: {err}"),
+        Err(_) => println!("Failed to stop devices"),
+    }
+}
+
+//What one connected device can do
+#[derive(Debug, Clone, PartialEq)]
+pub struct BPDeviceInfo {
+    //Identifies the device to the backend, unlike its position in the device list this stays put while other devices come and go
+    pub index: usize,
+    pub name: String,
+    pub vibrator_count: usize,
+    //Distinct strengths each vibrator supports, 0 when unknown
+    pub vibrator_steps: Vec<u32>,
+    pub linear_count: usize,
+    pub rotator_count: usize,
+}
+
+//Whatever the simulator's output ends up driving, so the app doesn't depend on Intiface being there
+pub trait BPOutputBackend {
+    fn connect(&mut self) -> Result<(), String>;
+    fn is_connected(&self) -> bool;
+    fn devices(&self) -> Vec<BPDeviceInfo>;
+    //One strength per vibrator of the device
+    fn set_scalar(&mut self, device: usize, strengths: Vec<f64>);
+    //Moves a stroker to position (0 to 1) over duration
+    fn linear(&mut self, device: usize, duration: Duration, position: f64);
+    fn rotate(&mut self, device: usize, speed: f64, clockwise: bool);
+    //Stops every device right away
+    fn stop(&mut self);
+    //Stops every device and disconnects, safe to call more than once
+    fn shutdown(&mut self);
+}
+
+//Where BPIntifaceClient finds its buttplug server
+#[derive(Debug, Clone, Default)]
+pub enum BPConnectorChoice {
+    //Intiface Central over WebSocket at ws://localhost:12345
+    #[default]
+    WebSocket,
+    //A server inside this process, only seeing the given simulated devices
+    InProcess(BPSimulatedDevices),
+}
+
+//One write the in-process server made to a simulated device
+#[derive(Debug, Clone, PartialEq)]
+pub struct BPHardwareWrite {
+    pub device: String,
+    pub data: Vec<u8>,
+}
+
+//Simulated hardware for the in-process server, named like real Bluetooth devices so buttplug picks their protocol
+#[derive(Debug, Clone, Default)]
+pub struct BPSimulatedDevices {
+    names: Vec<String>,
+    writes: std::sync::Arc<std::sync::Mutex<Vec<BPHardwareWrite>>>,
+}
+
+impl BPSimulatedDevices {
+    pub fn new(names: &[&str]) -> BPSimulatedDevices {
+        BPSimulatedDevices {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            writes: std::sync::Arc::new(std::sync::Mutex::new(Vec::new())),
+        }
+    }
+
+    //Every raw write the devices received so far, oldest first
+    pub fn writes(&self) -> Vec<BPHardwareWrite> {
+        self.writes.lock().unwrap().clone()
+    }
+}
+
+//Hands the simulated devices to the server as soon as it scans
+struct BPSimulatedCommManager {
+    sender: tokio::sync::mpsc::Sender<HardwareCommunicationManagerEvent>,
+    devices: BPSimulatedDevices,
+}
+
+impl HardwareCommunicationManagerBuilder for BPSimulatedDevices {
+    fn finish(&mut self, sender: tokio::sync::mpsc::Sender<HardwareCommunicationManagerEvent>) -> Box<dyn HardwareCommunicationManager> {
+        Box::new(BPSimulatedCommManager {
+            sender,
+            devices: self.clone(),
+        })
+    }
+}
+
+impl HardwareCommunicationManager for BPSimulatedCommManager {
+    fn name(&self) -> &'static str {
+        "BPSimulatedCommManager"
+    }
+    fn start_scanning(&mut self) -> ButtplugResultFuture {
+        let sender = self.sender.clone();
+        let devices = self.devices.clone();
+        async move {
+            for (index, name) in devices.names.iter().enumerate() {
+                let connector = BPSimulatedHardware {
+                    name: name.clone(),
+                    address: format!("simulated-{index}"),
+                    endpoints: Vec::new(),
+                    writes: devices.writes.clone(),
+                    events: tokio::sync::broadcast::channel(16).0,
+                };
+                let event = HardwareCommunicationManagerEvent::DeviceFound {
+                    name: connector.name.clone(),
+                    address: connector.address.clone(),
+                    creator: Box::new(connector),
+                };
+                if sender.send(event).await.is_err() {
+                    println!("Simulated device channel closed");
+                }
+            }
+            let _ = sender.send(HardwareCommunicationManagerEvent::ScanningFinished).await;
+            Ok(())
+        }.boxed()
+    }
+    fn stop_scanning(&mut self) -> ButtplugResultFuture {
+        futures::future::ready(Ok(())).boxed()
+    }
+    fn can_scan(&self) -> bool {
+        true
+    }
+}
+
+//A single simulated device, acting as its own connector and specializer
+#[derive(Clone)]
+struct BPSimulatedHardware {
+    name: String,
+    address: String,
+    endpoints: Vec<Endpoint>,
+    writes: std::sync::Arc<std::sync::Mutex<Vec<BPHardwareWrite>>>,
+    events: tokio::sync::broadcast::Sender<HardwareEvent>,
+}
+
+impl std::fmt::Debug for BPSimulatedHardware {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BPSimulatedHardware").field("name", &self.name).field("address", &self.address).finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl HardwareConnector for BPSimulatedHardware {
+    fn specifier(&self) -> ProtocolCommunicationSpecifier {
+        ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new_from_device(&self.name, &HashMap::new(), &[]))
+    }
+    async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+        Ok(Box::new(self.clone()))
+    }
+}
+
+#[async_trait::async_trait]
+impl HardwareSpecializer for BPSimulatedHardware {
+    async fn specialize(&mut self, specifiers: &[ProtocolCommunicationSpecifier]) -> Result<Hardware, ButtplugDeviceError> {
+        //Offer every endpoint the protocol asks for
+        for specifier in specifiers {
+            if let ProtocolCommunicationSpecifier::BluetoothLE(btle) = specifier {
+                for endpoint_map in btle.services().values() {
+                    self.endpoints.extend(endpoint_map.keys().copied());
+                }
+            }
+        }
+        Ok(Hardware::new(&self.name, &self.address, &self.endpoints, Box::new(self.clone())))
+    }
+}
+
+impl HardwareInternal for BPSimulatedHardware {
+    fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+        let _ = self.events.send(HardwareEvent::Disconnected(self.address.clone()));
+        futures::future::ready(Ok(())).boxed()
+    }
+    fn event_stream(&self) -> tokio::sync::broadcast::Receiver<HardwareEvent> {
+        self.events.subscribe()
+    }
+    fn read_value(&self, msg: &HardwareReadCmd) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+        futures::future::ready(Ok(HardwareReading::new(msg.endpoint(), &[]))).boxed()
+    }
+    fn write_value(&self, msg: &HardwareWriteCmd) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+        self.writes.lock().unwrap().push(BPHardwareWrite {
+            device: self.name.clone(),
+            data: msg.data().clone(),
+        });
+        futures::future::ready(Ok(())).boxed()
+    }
+    fn subscribe(&self, _msg: &HardwareSubscribeCmd) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+        futures::future::ready(Ok(())).boxed()
+    }
+    fn unsubscribe(&self, _msg: &HardwareUnsubscribeCmd) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+        futures::future::ready(Ok(())).boxed()
     }
 }
+
+#[derive(Default)]
 pub struct BPIntifaceClient {
-    client: Option<ButtplugClient>,
+    client: Option<std::sync::Arc<ButtplugClient>>,
     rt: Option<Runtime>,
+    connector: BPConnectorChoice,
+}
+
+//Whatever ends the client, stop the devices first so nothing is left running
+impl Drop for BPIntifaceClient {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
 }
 
 impl BPIntifaceClient {
+    pub fn with_connector(connector: BPConnectorChoice) -> BPIntifaceClient {
+        BPIntifaceClient {
+            client: None,
+            rt: None,
+            connector,
+        }
+    }
     pub fn test() {
         let rt = tokio::runtime::Builder::new_current_thread()
             .enable_all()
@@ -860,54 +3568,181 @@ impl BPIntifaceClient {
         // Call the asynchronous connect method using the runtime.
         let _inner = rt.block_on(test_buttplug());
     }
-    pub fn connect(&mut self) {
-        self.rt = Some(
-            tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .unwrap(),
-        );
-
-        // Call the asynchronous connect method using the runtime.
-        self.client = Some(
-            self.rt
-                .as_mut()
-                .unwrap()
-                .block_on(connect_buttplug())
-                .unwrap(),
-        );
-    }
     pub fn vibrate(&mut self) {
         self.rt
             .as_mut()
             .unwrap()
             .block_on(vibrate_buttplug(&self.client.as_mut().unwrap()));
     }
-    pub fn stop(&mut self) {
-        self.rt
-            .as_mut()
-            .unwrap()
-            .block_on(stop_buttplug(&self.client.as_mut().unwrap()));
+    //Runs a device command to completion, reporting failures instead of panicking
+    fn run_device_command<F>(&mut self, command: impl FnOnce(std::sync::Arc<ButtplugClient>) -> F)
+    where
+        F: std::future::Future<Output = Result<(), ButtplugClientError>>,
+    {
+        match (self.rt.as_mut(), self.client.as_ref())
+        {
+            (Some(rt), Some(client)) => {
+                if let Err(err) = rt.block_on(command(client.clone()))
+                {
+                    println!("Device command failed: {err:?}");
+                }
+            }
+            _ => println!("Client not connected!"),
+        }
     }
+}
 
-    pub fn set_device_vibration_strengths(&mut self, strengths:Vec<f64>)
-    {
-      self.rt
-            .as_mut()
-            .unwrap()
-            .block_on(device_set_vibration_strengths(&self.client.as_mut().unwrap(), strengths));
+impl BPOutputBackend for BPIntifaceClient {
+    fn connect(&mut self) -> Result<(), String> {
+        //The client's event loop is spawned on this runtime, it needs a worker of its own
+        //so the crash handler can still stop devices while this thread is stuck
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .map_err(|err| err.to_string())?;
+
+        // Call the asynchronous connect method using the runtime.
+        let client = rt.block_on(connect_buttplug(&self.connector)).map_err(|err| format!("{err:?}"))?;
+        self.rt = Some(rt);
+        self.client = Some(std::sync::Arc::new(client));
+        DEVICE_STOP_CLIENT.lock().unwrap().clone_from(&self.client);
+        Ok(())
     }
-    pub fn num_vibrator_motors(&mut self) -> usize
-    {
+    fn is_connected(&self) -> bool {
+        self.client.as_ref().is_some_and(|client| client.connected())
+    }
+    fn devices(&self) -> Vec<BPDeviceInfo> {
         match &self.client
         {
-            None => {
-                println!("Client not connected!");
-                0 as usize
-            }
-            Some(bp_client) => {
-                bp_client.devices()[0].vibrate_attributes().len()
-            }
+            None => Vec::new(),
+            Some(bp_client) => bp_client.devices().iter().map(|device| BPDeviceInfo {
+                index: device.index() as usize,
+                name: device.name().clone(),
+                vibrator_count: device.vibrate_attributes().len(),
+                vibrator_steps: device.vibrate_attributes().iter().map(|attributes| *attributes.step_count()).collect(),
+                linear_count: device.linear_attributes().len(),
+                rotator_count: device.rotate_attributes().len(),
+            }).collect(),
+        }
+    }
+    fn set_scalar(&mut self, device: usize, strengths: Vec<f64>) {
+        self.run_device_command(|client| async move { device_set_vibration_strengths(&client, device, strengths).await });
+    }
+    fn linear(&mut self, device: usize, duration: Duration, position: f64) {
+        self.run_device_command(|client| async move { device_linear(&client, device, duration, position).await });
+    }
+    fn rotate(&mut self, device: usize, speed: f64, clockwise: bool) {
+        self.run_device_command(|client| async move { device_rotate(&client, device, speed, clockwise).await });
+    }
+    fn stop(&mut self) {
+        self.run_device_command(|client| async move { client.stop_all_devices().await });
+    }
+    fn shutdown(&mut self) {
+        let (rt, client) = match (self.rt.as_mut(), self.client.take()) {
+            (Some(rt), Some(client)) => (rt, client),
+            _ => return,
+        };
+        if let Ok(mut stop_client) = DEVICE_STOP_CLIENT.lock() {
+            *stop_client = None;
+        }
+        if !client.connected() {
+            println!("Intiface connection already lost, devices can't be stopped from here");
+            return;
+        }
+        println!("Shutting down Intiface client");
+        if let Err(err) = rt.block_on(client.stop_all_devices()) {
+            println!("Failed to stop devices: {err:?}");
+        }
+        if let Err(err) = rt.block_on(client.disconnect()) {
+            println!("Failed to disconnect: {err:?}");
+        }
+    }
+}
+
+//Matches the 20 steps common on Lovense vibrators
+const VIRTUAL_DEVICE_STEPS: u32 = 20;
+//Oldest commands are forgotten past this, so a long session doesn't grow the recording forever
+const VIRTUAL_DEVICE_MAX_RECORDED: usize = 10000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BPBackendCommand {
+    Scalar { device: usize, strengths: Vec<f64> },
+    Linear { device: usize, duration: Duration, position: f64 },
+    Rotate { device: usize, speed: f64, clockwise: bool },
+    Stop,
+}
+
+//Pretend multi-motor device that records every command it gets, for tests and development without Bluetooth
+#[derive(Debug)]
+pub struct BPVirtualDevice {
+    info: BPDeviceInfo,
+    connected: bool,
+    commands: VecDeque<(Instant, BPBackendCommand)>,
+}
+
+impl BPVirtualDevice {
+    pub fn new(vibrator_count: usize) -> BPVirtualDevice {
+        BPVirtualDevice {
+            info: BPDeviceInfo {
+                index: 0,
+                name: "Virtual Device".to_string(),
+                vibrator_count,
+                vibrator_steps: vec![VIRTUAL_DEVICE_STEPS; vibrator_count],
+                linear_count: 1,
+                rotator_count: 1,
+            },
+            connected: false,
+            commands: VecDeque::new(),
+        }
+    }
+
+    //Every command received so far with the instant it arrived, oldest first
+    pub fn commands(&self) -> &VecDeque<(Instant, BPBackendCommand)> {
+        &self.commands
+    }
+
+    fn record(&mut self, device: usize, command: BPBackendCommand) {
+        if device != 0 {
+            println!("Virtual device {} doesn't exist, ignoring {:?}", device, command);
+            return;
+        }
+        if self.commands.len() >= VIRTUAL_DEVICE_MAX_RECORDED {
+            self.commands.pop_front();
+        }
+        self.commands.push_back((Instant::now(), command));
+    }
+}
+
+impl BPOutputBackend for BPVirtualDevice {
+    fn connect(&mut self) -> Result<(), String> {
+        println!("Connected to {} with {} vibrators", self.info.name, self.info.vibrator_count);
+        self.connected = true;
+        Ok(())
+    }
+    fn is_connected(&self) -> bool {
+        self.connected
+    }
+    fn devices(&self) -> Vec<BPDeviceInfo> {
+        if self.connected { vec![self.info.clone()] } else { Vec::new() }
+    }
+    fn set_scalar(&mut self, device: usize, mut strengths: Vec<f64>) {
+        strengths.resize(self.info.vibrator_count, 0.0);
+        self.record(device, BPBackendCommand::Scalar { device, strengths });
+    }
+    fn linear(&mut self, device: usize, duration: Duration, position: f64) {
+        self.record(device, BPBackendCommand::Linear { device, duration, position });
+    }
+    fn rotate(&mut self, device: usize, speed: f64, clockwise: bool) {
+        self.record(device, BPBackendCommand::Rotate { device, speed, clockwise });
+    }
+    fn stop(&mut self) {
+        self.record(0, BPBackendCommand::Stop);
+    }
+    fn shutdown(&mut self) {
+        if self.connected {
+            self.stop();
+            self.connected = false;
         }
     }
 }
@@ -915,16 +3750,36 @@ impl BPIntifaceClient {
 struct MyApp {
     name: String,
     age: u32,
-    bp_client: Option<BPIntifaceClient>,
+    output_backend: Option<Box<dyn BPOutputBackend>>,
+    //The devices the simulator's motors were laid out for when the backend connected, in motor order
+    device_layout: Vec<BPDeviceInfo>,
     bp_sim: BPSimulator,
-    bp_parser: BPDataParser,
+    bp_sources: Vec<BPCommandSource>,
+    new_source_path: String,
     update_ticks: u32,
-    device_order_period: Duration,
-    device_last_order_instant: Instant,
+    output_scheduler: BPOutputScheduler,
     // file_text: Option<String>,
     debug_event_millis: u64,
     debug_event_strength: f64,
     debug_stats_vibrator_motor_states: Vec<f64>,
+    //Pause the simulation when the game frame stops progressing
+    auto_pause_on_stall: bool,
+    auto_pause_stall_secs: u64,
+    auto_paused: bool,
+    stall_handled: bool,
+    //Rebuild still running events from the log on connect instead of skipping it
+    catch_up_on_connect: bool,
+    safety_limiter: BPSafetyLimiter,
+    input_watchdog: BPInputWatchdog,
+    wellness_timer: BPWellnessTimer,
+    //Present while dry running, the pipeline then runs without a device
+    output_quantizer: BPOutputQuantizer,
+    dry_run_log: Option<BPDryRunLog>,
+    //Motors simulated by dry runs and the virtual device
+    virtual_motor_count: usize,
+    //Set by a panic stop, keeps all output muted until re-armed by hand
+    panic_latched: bool,
+    panic_key: egui::Key,
 }
 
 impl Default for MyApp {
@@ -932,16 +3787,30 @@ impl Default for MyApp {
         Self {
             name: "Arthur".to_owned(),
             age: 42,
-            bp_client: None,
-            bp_sim: Default::default(),
-            bp_parser: BPDataParser::new("cmdlog.txt".to_string()),
+            output_backend: None,
+            device_layout: Vec::new(),
+            bp_sim: BPSimulator::with_clock(Box::new(BPSystemClock)),
+            bp_sources: vec![BPCommandSource::new("cmdlog.txt".to_string())],
+            new_source_path: String::new(),
             update_ticks: 0,
-            device_order_period: Duration::from_millis(100),
-            device_last_order_instant: Instant::now(),
+            output_scheduler: BPOutputScheduler::default(),
             // file_text: None,
             debug_event_millis: 500,
             debug_event_strength: 0.5,
             debug_stats_vibrator_motor_states: [0 as f64].to_vec(),
+            auto_pause_on_stall: false,
+            auto_pause_stall_secs: 10,
+            auto_paused: false,
+            stall_handled: false,
+            catch_up_on_connect: false,
+            safety_limiter: Default::default(),
+            input_watchdog: Default::default(),
+            wellness_timer: Default::default(),
+            dry_run_log: None,
+            virtual_motor_count: 2,
+            output_quantizer: BPOutputQuantizer::default(),
+            panic_latched: false,
+            panic_key: egui::Key::Escape,
         }
     }
 
@@ -954,76 +3823,456 @@ impl Default for MyApp {
     // }
 }
 
+//Keys offered for the panic stop shortcut
+const PANIC_KEY_CHOICES: [egui::Key; 6] = [
+    egui::Key::Escape,
+    egui::Key::Space,
+    egui::Key::Backspace,
+    egui::Key::F9,
+    egui::Key::F10,
+    egui::Key::F12,
+];
+
+impl MyApp {
+    //Stops every device immediately and mutes everything until re-armed
+    fn panic_stop(&mut self) {
+        println!("PANIC STOP");
+        self.panic_latched = true;
+        self.bp_sim.force_stop();
+        self.debug_stats_vibrator_motor_states.iter_mut().for_each(|intensity| *intensity = 0.0);
+        if let Some(output_backend) = self.output_backend.as_mut() {
+            output_backend.stop();
+        }
+    }
+
+    //Time since the most recent input from any enabled source
+    fn input_idle_for(sources: &[BPCommandSource]) -> Duration {
+        sources.iter()
+            .filter(|source| source.enabled)
+            .map(|source| source.parser.time_since_input())
+            .min()
+            .unwrap_or(Duration::ZERO)
+    }
+
+    //Only a pause asked for by the game or the user that actually silences the output holds the watchdog,
+    //a stall auto-pause is exactly the situation the watchdog is there for
+    fn input_watchdog_held(bp_sim: &BPSimulator, auto_paused: bool) -> bool {
+        bp_sim.is_silenced() && !auto_paused
+    }
+
+    //Replaces the current output with a newly connected one, sized to all of its devices
+    fn connect_backend(&mut self, mut output_backend: Box<dyn BPOutputBackend>) {
+        self.dry_run_log = None;
+        //Drop the old backend first, stopping its devices
+        self.output_backend = None;
+        if let Err(err) = output_backend.connect() {
+            println!("Could not connect: {err}");
+            return;
+        }
+        //The simulator's motors are laid out device after device
+        self.device_layout = output_backend.devices();
+        let num_motors = self.device_layout.iter().map(|device| device.vibrator_count).sum();
+        self.output_backend = Some(output_backend);
+        self.start_pipeline(num_motors);
+    }
+
+    //Sets the simulator up for a fresh set of motors and brings every source up to date
+    fn start_pipeline(&mut self, num_motors: usize) {
+        self.output_quantizer.reset();
+        self.output_scheduler.reset();
+        self.bp_sim.reset_for_new_device();
+        self.bp_sim.add_multiple_vib_effectors(num_motors);
+        let shared_sim = self.bp_sources.len() > 1;
+        for source in self.bp_sources.iter_mut() {
+            if self.catch_up_on_connect {
+                source.parser.max_catch_up_age = self.input_watchdog.idle_timeout;
+                self.bp_sim.add_event_queue(source.catch_up_current_game(shared_sim));
+            }
+            else {
+                source.parser.set_prev_event_to_latest();
+            }
+        }
+    }
+
+    fn rearm(&mut self) {
+        println!("Re-armed after panic stop");
+        self.bp_sim.force_stop();
+        self.output_quantizer.reset();
+        self.panic_latched = false;
+    }
+}
+
 impl eframe::App for MyApp {
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        println!("Closing, stopping all devices");
+        if let Some(mut output_backend) = self.output_backend.take() {
+            output_backend.shutdown();
+        }
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.update_ticks += 1;
-        match self.bp_client.as_mut()
+        if ctx.input(|input| input.key_pressed(self.panic_key))
+        {
+          self.panic_stop();
+        }
+        if self.output_backend.as_ref().is_some_and(|output_backend| !output_backend.is_connected())
+        {
+          println!("Lost connection to the output devices");
+          self.output_backend = None;
+          self.bp_sim.force_stop();
+        }
+        let mut panic_requested = false;
+        //Dry runs drive the same pipeline, just without a device at the end
+        if self.output_backend.is_some() || self.dry_run_log.is_some()
         {
-          None => {},
-          Some(client) => {
-            self.bp_sim.add_event_queue(self.bp_parser.get_new_events());
-            self.bp_sim.process_tick(std::time::Instant::now());
-            if(Instant::now() - self.device_last_order_instant >= self.device_order_period)
+          let shared_sim = self.bp_sources.len() > 1;
+          for source in self.bp_sources.iter_mut()
+          {
+            let event_queue = source.get_new_events(shared_sim);
+            if event_queue.iter().any(|bpevent| matches!(bpevent.action, BPActionType::Panic))
+            {
+              println!("PANIC recieved from {}", source.name);
+              panic_requested = true;
+              self.panic_latched = true;
+            }
+            //Nothing reaches the simulator until re-armed
+            if !self.panic_latched
+            {
+              let event_queue = self.wellness_timer.filter_events(event_queue, &self.bp_sim);
+              if let Some(dry_run_log) = self.dry_run_log.as_mut()
+              {
+                dry_run_log.log_events(&event_queue);
+              }
+              self.bp_sim.add_event_queue(event_queue);
+            }
+          }
+          //A paused game usually writes nothing, and its persistent events have to survive until RESUME
+          let watchdog_event = if MyApp::input_watchdog_held(&self.bp_sim, self.auto_paused)
+          {
+            self.input_watchdog.hold(Instant::now());
+            None
+          }
+          else
+          {
+            self.input_watchdog.update(MyApp::input_idle_for(&self.bp_sources), Instant::now())
+          };
+          match watchdog_event
+          {
+            None => {},
+            Some(BPWatchdogEvent::Tripped) => {
+              println!("No input for {:?}, fading out", self.input_watchdog.idle_timeout);
+              for source in self.bp_sources.iter_mut()
+              {
+                source.parser.end_session_for_inactivity();
+              }
+            },
+            Some(BPWatchdogEvent::FadedOut) => {
+              //Otherwise long POWER events would come back at full strength with the next command
+              self.bp_sim.force_stop();
+            },
+            Some(BPWatchdogEvent::Resumed) => {
+              println!("Input is back, watchdog resumed");
+            },
+          }
+          let stall_timeout = Duration::from_secs(self.auto_pause_stall_secs);
+          let stalled = self.bp_sources.iter()
+            .filter(|source| source.enabled)
+            .all(|source| source.parser.time_since_frame_progress() >= stall_timeout);
+          //Only pause once per stall, so unpausing by hand sticks
+          if stalled
+          {
+            if self.auto_pause_on_stall && !self.stall_handled && !self.bp_sim.is_paused()
+            {
+              println!("Game frame hasn't progressed in {} seconds, pausing", self.auto_pause_stall_secs);
+              self.bp_sim.set_paused(true);
+              self.auto_paused = true;
+              self.stall_handled = true;
+            }
+          }
+          else
+          {
+            self.stall_handled = false;
+            if self.auto_paused
+            {
+              self.bp_sim.set_paused(false);
+              self.auto_paused = false;
+            }
+          }
+          self.bp_sim.tick();
+          let order_instant = Instant::now();
+          let connected_devices: Vec<usize> = self.output_backend.as_ref()
+            .map(|output_backend| output_backend.devices().iter().map(|device| device.index).collect())
+            .unwrap_or_default();
+          //Devices that disconnected since keep their motors, so the others' motors don't shift onto the wrong device
+          let mut output_devices: Vec<usize> = self.device_layout.iter()
+            .filter(|device| device.vibrator_count > 0 && connected_devices.contains(&device.index))
+            .map(|device| device.index)
+            .collect();
+          //Dry runs and backends without devices are scheduled like a single device
+          if output_devices.is_empty()
+          {
+            output_devices.push(0);
+          }
+          let due_devices: Vec<usize> = output_devices.into_iter()
+            .filter(|device| self.output_scheduler.is_due(*device, order_instant))
+            .collect();
+          if(!self.panic_latched && !due_devices.is_empty())
+          {
+            let mut write_latencies: HashMap<usize, Duration> = HashMap::new();
+            let watchdog_scale = self.input_watchdog.output_scale(Instant::now());
+            let intensities: Vec<f64> = self.bp_sim.get_vibrator_intensities().iter().map(|intensity| intensity * watchdog_scale).collect();
+            let intensities = self.wellness_timer.apply(intensities, Instant::now());
+            let limited_intensities = self.safety_limiter.apply(intensities, Instant::now());
+            if let Some(output_backend) = self.output_backend.as_mut()
+            {
+              for (device, strengths) in split_motors_by_device(&limited_intensities, &self.device_layout)
+              {
+                if !due_devices.contains(&device.index)
+                {
+                  continue;
+                }
+                if let Some(strengths) = self.output_quantizer.prepare(device.index, &strengths, &device.vibrator_steps, order_instant)
+                {
+                  let write_start = Instant::now();
+                  output_backend.set_scalar(device.index, strengths);
+                  write_latencies.insert(device.index, write_start.elapsed());
+                }
+              }
+            }
+            if let Some(dry_run_log) = self.dry_run_log.as_mut()
             {
-              self.device_last_order_instant = Instant::now();
-              client.set_device_vibration_strengths(self.bp_sim.get_vibrator_intensities());
-              self.debug_stats_vibrator_motor_states = self.bp_sim.get_vibrator_intensities();
+              dry_run_log.log_output(&limited_intensities);
+              write_latencies.insert(0, Duration::ZERO);
             }
-          },
-        };
+            for device in due_devices
+            {
+              self.output_scheduler.record(device, order_instant, write_latencies.get(&device).copied());
+            }
+            self.debug_stats_vibrator_motor_states = limited_intensities;
+          }
+        }
+        if panic_requested
+        {
+          self.panic_stop();
+        }
         ctx.request_repaint_after(std::time::Duration::from_micros(
             (1.0 / 60.0 * 1000000.0) as u64,
         ));
-        egui::CentralPanel::default().show(ctx, |ui| {
+        //Panic stop lives outside the scrolling settings so it is always on screen
+        egui::TopBottomPanel::top("panic_panel").show(ctx, |ui| {
             ui.heading("Beyond All Buttplug Client");
-            // ui.horizontal(|ui| {
-            //     let name_label = ui.label("Your name: ");
-            //     ui.text_edit_singleline(&mut self.name)
-            //         .labelled_by(name_label.id);
-            // });
-            // ui.add(egui::Slider::new(&mut self.age, 0..=120).text("age"));
-            // if ui.button("Increment").clicked() {
-            //     self.age += 1;
-            //     // self.bp_client.as_mut().unwrap().vibrate();
-            // }
-            if ui.button("Add Debug Stop").clicked() {
-                self.bp_sim.add_event(BPSimEvent::new_stop_event());
-              }
-            if ui.button("Connect").clicked() {
-                self.bp_client = Some(BPIntifaceClient {
-                    client: None,
-                    rt: None,
+            let panic_button = egui::Button::new(egui::RichText::new("PANIC STOP").size(28.0).color(egui::Color32::WHITE))
+                .fill(egui::Color32::from_rgb(200, 0, 0));
+            if ui.add_sized([240.0, 64.0], panic_button).clicked() {
+                self.panic_stop();
+            }
+            if self.panic_latched {
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new("Output muted by panic stop").color(egui::Color32::RED));
+                    if ui.button("Re-arm").clicked() {
+                        self.rearm();
+                    }
                 });
-                self.bp_client.as_mut().unwrap().connect();
-                self.bp_sim.reset_for_new_device();
-                self.bp_sim.add_multiple_vib_effectors(self.bp_client.as_mut().unwrap().num_vibrator_motors());
-                self.bp_parser.set_prev_event_to_latest();
             }
-            // if ui.button("Display File").clicked() {
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            //Settings no longer fit the window, so let them scroll
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Panic shortcut:");
+                    egui::ComboBox::from_id_source("panic_key")
+                        .selected_text(self.panic_key.name())
+                        .show_ui(ui, |ui| {
+                            for key in PANIC_KEY_CHOICES {
+                                ui.selectable_value(&mut self.panic_key, key, key.name());
+                            }
+                        });
+                });
+                // ui.horizontal(|ui| {
+                //     let name_label = ui.label("Your name: ");
+                //     ui.text_edit_singleline(&mut self.name)
+                //         .labelled_by(name_label.id);
+                // });
+                // ui.add(egui::Slider::new(&mut self.age, 0..=120).text("age"));
+                // if ui.button("Increment").clicked() {
+                //     self.age += 1;
+                //     // self.bp_client.as_mut().unwrap().vibrate();
+                // }
+                if ui.button("Add Debug Stop").clicked() {
+                    self.bp_sim.add_event(BPSimEvent::new_stop_event());
+                  }
+                if ui.button("Connect").clicked() {
+                    self.connect_backend(Box::<BPIntifaceClient>::default());
+                }
+                if let Some(output_backend) = self.output_backend.as_ref() {
+                    for device in output_backend.devices() {
+                        ui.label(format!("{}: {} vibrators, {} linear, {} rotators", device.name, device.vibrator_count, device.linear_count, device.rotator_count));
+                    }
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Connect Virtual Device").clicked() {
+                        self.connect_backend(Box::new(BPVirtualDevice::new(self.virtual_motor_count)));
+                    }
+                    if ui.button("Connect Simulated Buttplug Device").clicked() {
+                        let simulated_devices = BPSimulatedDevices::new(&["Massage Demo"]);
+                        self.connect_backend(Box::new(BPIntifaceClient::with_connector(BPConnectorChoice::InProcess(simulated_devices))));
+                    }
+                    if self.dry_run_log.is_none() {
+                        if ui.button("Start Dry Run").clicked() {
+                            match BPDryRunLog::create(DRY_RUN_LOG_PATH) {
+                                Err(err) => println!("Could not create dry run log: {err}"),
+                                Ok(dry_run_log) => {
+                                    self.output_backend = None;
+                                    self.dry_run_log = Some(dry_run_log);
+                                    self.start_pipeline(self.virtual_motor_count);
+                                }
+                            }
+                        }
+                    }
+                    else if ui.button("Stop Dry Run").clicked() {
+                        self.dry_run_log = None;
+                        self.bp_sim.force_stop();
+                    }
+                });
+                ui.add(egui::Slider::new(&mut self.virtual_motor_count, 1..=4).text("Virtual motors"));
+                if self.dry_run_log.is_some() {
+                    ui.label(format!("Dry run, output is logged to {} instead of a device", DRY_RUN_LOG_PATH));
+                }
+                ui.checkbox(&mut self.catch_up_on_connect, "Catch up on the current game when connecting");
+                let mut paused = self.bp_sim.is_paused();
+                if ui.checkbox(&mut paused, "Paused").changed() {
+                    self.bp_sim.set_paused(paused);
+                    self.auto_paused = false;
+                }
+                ui.checkbox(&mut self.bp_sim.silence_when_paused, "Silence while paused");
+                ui.checkbox(&mut self.auto_pause_on_stall, "Auto-pause when the game stalls");
+                ui.add(egui::Slider::new(&mut self.auto_pause_stall_secs, 1..=120).text("Stall timeout (seconds)"));
+                egui::CollapsingHeader::new("Mixing").show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Default policy:");
+                        let mut default_policy = Some(self.bp_sim.mix_policy);
+                        if BPMixPolicy::show_combo(ui, "mix_policy_default".to_string(), &mut default_policy, false) {
+                            self.bp_sim.set_mix_policy(default_policy.unwrap());
+                        }
+                    });
+                    ui.add(egui::Slider::new(&mut self.bp_sim.mix_duck_factor, 0.0..=1.0).text("Duck factor"));
+                    let motors: Vec<i8> = self.bp_sim.effectors.iter()
+                        .filter(|effector| matches!(effector.effector_type, BPEffectorType::Vibrates { .. }))
+                        .map(|effector| effector.index)
+                        .collect();
+                    for motor in motors {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("Motor {}:", motor));
+                            let mut motor_policy = self.bp_sim.motor_mix_policies.get(&motor).copied();
+                            if BPMixPolicy::show_combo(ui, format!("mix_policy_motor_{}", motor), &mut motor_policy, true) {
+                                self.bp_sim.set_motor_mix_policy(motor, motor_policy);
+                            }
+                        });
+                    }
+                    for (tag, policy) in self.bp_sim.tag_mix_policies.iter() {
+                        ui.label(format!("Tag {}: {:?}", tag, policy));
+                    }
+                });
+                egui::CollapsingHeader::new("Input watchdog").show(ui, |ui| {
+                    self.input_watchdog.show_ui(ui, MyApp::input_idle_for(&self.bp_sources));
+                });
+                egui::CollapsingHeader::new("Session timers").show(ui, |ui| {
+                    self.wellness_timer.show_ui(ui);
+                });
+                egui::CollapsingHeader::new("Safety limits").show(ui, |ui| {
+                    self.safety_limiter.show_ui(ui, self.bp_sim.effectors.len());
+                });
+                egui::CollapsingHeader::new("Device writes").show(ui, |ui| {
+                    self.output_quantizer.show_ui(ui);
+                    self.output_scheduler.show_ui(ui);
+                });
+                egui::CollapsingHeader::new("Decay").show(ui, |ui| {
+                    let mut changed_model: Option<(i8, &str)> = None;
+                    for effector in self.bp_sim.effectors.iter_mut() {
+                        ui.push_id(effector.index, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label(format!("Motor {}:", effector.index));
+                                let mut model_name = effector.decay_model.name();
+                                egui::ComboBox::from_id_source("decay_model")
+                                    .selected_text(model_name)
+                                    .show_ui(ui, |ui| {
+                                        for name in BP_DECAY_MODEL_NAMES {
+                                            ui.selectable_value(&mut model_name, name, name);
+                                        }
+                                    });
+                                if model_name != effector.decay_model.name() {
+                                    changed_model = Some((effector.index, model_name));
+                                }
+                            });
+                            effector.decay_model.show_ui(ui);
+                        });
+                    }
+                    if let Some((motor, model_name)) = changed_model {
+                        self.bp_sim.set_decay_model(motor, new_decay_model(model_name).unwrap());
+                    }
+                });
+                if ui.button("Reload Macros").clicked() {
+                    for source in self.bp_sources.iter_mut() {
+                        source.parser.set_macros(BPMacroTable::load(MACRO_FILE_PATH));
+                    }
+                }
+                //Command sources
+                let mut removed_source: Option<usize> = None;
+                for (source_index, source) in self.bp_sources.iter_mut().enumerate() {
+                    egui::CollapsingHeader::new(format!("Source: {}", source.name))
+                        .id_source(source_index)
+                        .show(ui, |ui| {
+                            let was_enabled = source.enabled;
+                            source.show_ui(ui);
+                            if was_enabled && !source.enabled {
+                                self.bp_sim.add_event(source.cancel_event());
+                            }
+                            if ui.button("Remove Source").clicked() {
+                                removed_source = Some(source_index);
+                            }
+                        });
+                }
+                if let Some(source_index) = removed_source {
+                    let source = self.bp_sources.remove(source_index);
+                    self.bp_sim.add_event(source.cancel_event());
+                }
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut self.new_source_path).desired_width(150.0));
+                    if ui.button("Add Source").clicked() && !self.new_source_path.is_empty() {
+                        let mut source = BPCommandSource::new(self.new_source_path.clone());
+                        source.parser.set_prev_event_to_latest();
+                        self.bp_sources.push(source);
+                        self.new_source_path.clear();
+                    }
+                });
+                // if ui.button("Display File").clicked() {
                 
-            //     self.bp_parser.debug_print_file();
-            // }
-            // if ui.button("Display File Backwards").clicked() {
-            //     self.bp_parser.debug_print_file_rev();
-            // }
-            //Debug panel
-            ui.add(egui::Slider::new(&mut self.debug_event_millis, 100..=5000).text("Debug Event Duration (millis)"));
-            ui.add(egui::Slider::new(&mut self.debug_event_strength, 0.001..=1.0).text("Debug Event Strength"));
-
-            if ui.button("Add Debug Event").clicked() {
-              self.bp_sim.add_event(
-                BPSimEvent::new(Duration::from_millis(self.debug_event_millis), BPActionType::Vibrate { strength: self.debug_event_strength, motor: -1 as i8 })
-              )
-            }
-            ui.label(format!("Vibrator states: {:?}", self.debug_stats_vibrator_motor_states));
-            // ui.label(format!("Hello '{}', age {}", self.name, self.age));
-            ui.label(format!("Ticks passed: {}", self.update_ticks));
-            // match &self.file_text {
-            //     None => ui.label(format!("No file currently loaded.")),
-            //     Some(text_string) => ui.label(format!("File contains:\n{}", text_string)),
-            // };
-
-            ui.image(egui::include_image!("../resources/neco.png"));
+                //     self.bp_parser.debug_print_file();
+                // }
+                // if ui.button("Display File Backwards").clicked() {
+                //     self.bp_parser.debug_print_file_rev();
+                // }
+                //Debug panel
+                ui.add(egui::Slider::new(&mut self.debug_event_millis, 100..=5000).text("Debug Event Duration (millis)"));
+                ui.add(egui::Slider::new(&mut self.debug_event_strength, 0.001..=1.0).text("Debug Event Strength"));
+
+                if ui.button("Add Debug Event").clicked() {
+                  self.bp_sim.add_event(
+                    BPSimEvent::new(Duration::from_millis(self.debug_event_millis), BPActionType::Vibrate { strength: self.debug_event_strength, motor: -1 as i8 })
+                  )
+                }
+                ui.label(format!("Vibrator states: {:?}", self.debug_stats_vibrator_motor_states));
+                ui.label(format!("Active events: {}, scheduled events: {}", self.bp_sim.events.len(), self.bp_sim.pending_events.len()));
+                // ui.label(format!("Hello '{}', age {}", self.name, self.age));
+                ui.label(format!("Ticks passed: {}", self.update_ticks));
+                // match &self.file_text {
+                //     None => ui.label(format!("No file currently loaded.")),
+                //     Some(text_string) => ui.label(format!("File contains:\n{}", text_string)),
+                // };
+
+                ui.image(egui::include_image!("../resources/neco.png"));
+            });
         });
     }
 }
@@ -1105,6 +4354,7 @@ fn main() -> Result<(), eframe::Error> {
     // First try at executing async code in sync context
     // let rt = tokio::runtime::Builder::new_current_thread().enable_all().build();
     // let inner = rt.block_on(test_buttplug());
+    install_crash_handler();
     BPIntifaceClient::test();
     // let bp_client = BPIntifaceClient;
     // bp_client.connect();
@@ -1243,20 +4493,41 @@ async fn test_buttplug() -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn connect_buttplug() -> Result<ButtplugClient, ButtplugClientError> {
+async fn connect_buttplug(connector_choice: &BPConnectorChoice) -> anyhow::Result<ButtplugClient> {
     println!("Attempting Connection");
-    let connector = new_json_ws_client_connector("ws://localhost:12345");
-
     let client = ButtplugClient::new("Beyond All Buttplug Client");
-    client.connect(connector).await?;
-
-    println!("Connected to Intiface");
+    match connector_choice
+    {
+        BPConnectorChoice::WebSocket => {
+            let connector = new_json_ws_client_connector("ws://localhost:12345");
+            client.connect(connector).await?;
+            println!("Connected to Intiface");
+        }
+        BPConnectorChoice::InProcess(simulated_devices) => {
+            let mut server_builder = ButtplugServerBuilder::default();
+            server_builder.comm_manager(simulated_devices.clone());
+            let connector = ButtplugInProcessClientConnectorBuilder::default()
+                .server(server_builder.finish()?)
+                .finish();
+            client.connect(connector).await?;
+            println!("Connected to in-process server");
+        }
+    }
 
     // You usually shouldn't run Start/Stop scanning back-to-back like
     // this, but with TestDevice we know our device will be found when we
     // call StartScanning, so we can get away with it.
     client.start_scanning().await?;
     client.stop_scanning().await?;
+    //Simulated devices still have to get through the server's protocol setup, give them a moment
+    if let BPConnectorChoice::InProcess(simulated_devices) = connector_choice
+    {
+        let started_at = Instant::now();
+        while client.devices().len() < simulated_devices.names.len() && started_at.elapsed() < Duration::from_secs(2)
+        {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    }
     println!("Client currently knows about these devices:");
     for device in client.devices() {
         println!("- {}", device.name());
@@ -1342,24 +4613,23 @@ async fn vibrate_buttplug(client: &ButtplugClient) -> Result<bool, ButtplugClien
     // }
 }
 
-async fn stop_buttplug(client: &ButtplugClient) -> Result<bool, ButtplugClientError> {
-    println!("Sending commands");
-
-    // Now that we know the message types for our connected device, we
-    // can send a message over! Seeing as we want to stick with the
-    // modern generic messages, we'll go with VibrateCmd.
-    //
-    // There's a couple of ways to send this message.
-    let test_client_device = &client.devices()[0];
-    test_client_device.stop().await?;
-    println!("Stopped");
-    Ok(true)
+//The connected device with the server's device index
+fn find_device(client: &ButtplugClient, device_index: usize) -> Option<std::sync::Arc<ButtplugClientDevice>>
+{
+  client.devices().into_iter().find(|device| device.index() as usize == device_index)
 }
 
-async fn device_set_vibration_strengths(client: &ButtplugClient, mut strengths: Vec<f64>) -> Result<(), ButtplugClientError>
+async fn device_set_vibration_strengths(client: &ButtplugClient, device_index: usize, mut strengths: Vec<f64>) -> Result<(), ButtplugClientError>
 {
 //   println!("Setting vibrators to: {strengths:?}");
-  let client_device = &client.devices()[0];
+  let client_device = match find_device(client, device_index)
+  {
+    None => {
+      println!("No device {} to vibrate", device_index);
+      return Ok(());
+    }
+    Some(device) => device.clone(),
+  };
   let vibrator_count = client_device.vibrate_attributes().len();
 //   println!(
 //       "{} has {} vibrators.",
@@ -1388,6 +4658,26 @@ async fn device_set_vibration_strengths(client: &ButtplugClient, mut strengths:
   Ok(())
 }
 
+async fn device_linear(client: &ButtplugClient, device_index: usize, duration: Duration, position: f64) -> Result<(), ButtplugClientError>
+{
+  match find_device(client, device_index)
+  {
+    None => println!("No device {} to move", device_index),
+    Some(device) => device.linear(&LinearCommand::Linear(duration.as_millis() as u32, position)).await?,
+  }
+  Ok(())
+}
+
+async fn device_rotate(client: &ButtplugClient, device_index: usize, speed: f64, clockwise: bool) -> Result<(), ButtplugClientError>
+{
+  match find_device(client, device_index)
+  {
+    None => println!("No device {} to rotate", device_index),
+    Some(device) => device.rotate(&RotateCommand::Rotate(speed, clockwise)).await?,
+  }
+  Ok(())
+}
+
 async fn device_stop(client: &ButtplugClient) -> Result<(), ButtplugClientError>
 {
   println!("Stopping all movement");
@@ -1412,6 +4702,35 @@ fn bad_add(a: i32, b: i32) -> i32 {
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
+    use proptest::prelude::*;
+
+    //Tests that connect a client share DEVICE_STOP_CLIENT, keep them from overwriting each other's
+    static DEVICE_STOP_CLIENT_TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    //Fresh uniquely named file in the temp dir, deleted again even when the test fails
+    struct TestTempFile {
+        path: PathBuf,
+    }
+
+    impl TestTempFile {
+        fn new(name: &str, contents: &str) -> TestTempFile {
+            static NEXT_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+            let id = NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let path = std::env::temp_dir().join(format!("bab_test_{}_{}_{}.txt", name, std::process::id(), id));
+            std::fs::write(&path, contents).unwrap();
+            TestTempFile { path }
+        }
+
+        fn path_string(&self) -> String {
+            self.path.to_string_lossy().to_string()
+        }
+    }
+
+    impl Drop for TestTempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
 
     #[test]
     fn test_add() {
@@ -1595,4 +4914,898 @@ mod tests {
             },
         }
     }
+    #[test]
+    fn test_bp_command_set_to_event() {
+        let bpcom = BPCommand::new("120 SET Key:eco_stall Strength:0.3".to_string()).unwrap();
+        let bp_sim_event = bpcom.to_event().unwrap();
+        assert_eq!(format!("{:?}", bp_sim_event.action), format!("{:?}", BPActionType::Set{ key: "eco_stall".to_string(), strength: 0.3, motor: -1 }));
+        assert_eq!(format!("{:?}", bp_sim_event.persistent), format!("{:?}", true));
+    }
+    #[test]
+    fn test_bp_sim_event_persistent_never_expires() {
+        let mut bp_sim_event = BPSimEvent::new_persistent(BPActionType::Power{ strength: 0.5, motor: -1 });
+        bp_sim_event.pass_time(Duration::from_secs(86400 * 7));
+        assert_eq!(format!("{:?}", bp_sim_event.finished), format!("{:?}", false));
+    }
+    //BP Simulator
+    #[test]
+    fn test_bp_simulator_keyed_event_updates_in_place() {
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(2);
+        bp_sim.add_event(BPSimEvent::new_persistent(BPActionType::Set{ key: "eco_stall".to_string(), strength: 0.3, motor: -1 }));
+        bp_sim.add_event(BPSimEvent::new_persistent(BPActionType::Set{ key: "eco_stall".to_string(), strength: 0.5, motor: 1 }));
+        bp_sim.add_event(BPSimEvent::new_persistent(BPActionType::Set{ key: "under_attack".to_string(), strength: 0.2, motor: 0 }));
+        assert_eq!(bp_sim.events.len(), 2);
+        assert!((bp_sim.mix_motor(0) - 0.2).abs() < 1e-9);
+        assert!((bp_sim.mix_motor(1) - 0.5).abs() < 1e-9);
+    }
+    #[test]
+    fn test_bp_simulator_clear_keyed_event() {
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(1);
+        bp_sim.add_event(BPSimEvent::new_persistent(BPActionType::Set{ key: "eco_stall".to_string(), strength: 0.3, motor: -1 }));
+        bp_sim.add_event(BPSimEvent::new_persistent(BPActionType::Set{ key: "under_attack".to_string(), strength: 0.2, motor: -1 }));
+        bp_sim.add_event(BPCommand::new("10 CLEAR Key:eco_stall".to_string()).unwrap().to_event().unwrap());
+        bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_millis(10));
+        assert_eq!(bp_sim.events.len(), 1);
+        assert!((bp_sim.mix_motor(0) - 0.2).abs() < 1e-9);
+    }
+    #[test]
+    fn test_bp_command_scheduling_args() {
+        let bpcom = BPCommand::new("300 VIBRATE Duration:0.5 Motor:-1 Strength:0.2 DelayFrames:15 Delay:1 Repeat:3".to_string()).unwrap();
+        let bp_sim_event = bpcom.to_event().unwrap();
+        assert_eq!(format!("{:?}", bp_sim_event.start_delay), format!("{:?}", Duration::from_millis(1500)));
+        assert_eq!(format!("{:?}", bp_sim_event.repeats_remaining), format!("{:?}", 2));
+        assert_eq!(format!("{:?}", bp_sim_event.repeat_interval), format!("{:?}", Duration::from_millis(500)));
+    }
+    #[test]
+    fn test_bp_command_negative_delay() {
+        let bpcom = BPCommand::new("300 VIBRATE Duration:0.5 Motor:-1 Strength:0.2 Delay:-1".to_string()).unwrap();
+        assert!(bpcom.to_event().is_none());
+    }
+    #[test]
+    fn test_bp_command_unrepresentable_times() {
+        for args in ["Delay:nan", "Delay:1e30", "DelayFrames:inf", "Repeat:2 Interval:1e30", "DurationFrames:1e300", "Duration:nan"] {
+            let bpcom = BPCommand::new(format!("300 VIBRATE Duration:0.5 Motor:-1 Strength:0.2 {}", args)).unwrap();
+            assert!(bpcom.to_event().is_none(), "{} was accepted", args);
+        }
+    }
+    #[test]
+    fn test_bp_simulator_delayed_repeating_event() {
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(1);
+        bp_sim.add_event(BPCommand::new("0 VIBRATE Duration:0.1 Motor:0 Strength:0.5 Delay:0.2 Repeat:2 Interval:0.3".to_string()).unwrap().to_event().unwrap());
+        assert_eq!(bp_sim.events.len(), 0);
+        assert_eq!(bp_sim.pending_events.len(), 1);
+        let start = bp_sim.last_sim_instant;
+        bp_sim.process_tick(start + Duration::from_millis(200));
+        assert_eq!(bp_sim.events.len(), 1);
+        assert_eq!(bp_sim.pending_events.len(), 1);
+        bp_sim.process_tick(start + Duration::from_millis(350));
+        assert_eq!(bp_sim.events.len(), 0);
+        bp_sim.process_tick(start + Duration::from_millis(500));
+        assert_eq!(bp_sim.events.len(), 1);
+        assert_eq!(bp_sim.pending_events.len(), 0);
+    }
+    //BP Macro Table
+    #[test]
+    fn test_bp_macro_expand_with_params() {
+        let mut macros = BPMacroTable::new();
+        macros.add_macro_line("# comment");
+        macros.add_macro_line("EXPLOSION VIBRATE Duration:0.3 Motor:-1 Strength:$Strength");
+        macros.add_macro_line("EXPLOSION VIBRATE Duration:1 Motor:$Motor Strength:$Strength*0.5 Delay:0.3");
+        let bpcom = BPCommand::new("1200 EXPLOSION Strength:0.8 Motor:1".to_string()).unwrap();
+        let bp_sim_events = bpcom.to_events(&macros, &BPGameClock::new());
+        assert_eq!(bp_sim_events.len(), 2);
+        assert_eq!(format!("{:?}", bp_sim_events[0].action), format!("{:?}", BPActionType::Vibrate{ strength: 0.8, motor: -1 }));
+        assert_eq!(format!("{:?}", bp_sim_events[1].action), format!("{:?}", BPActionType::Vibrate{ strength: 0.4, motor: 1 }));
+        assert_eq!(format!("{:?}", bp_sim_events[1].start_delay), format!("{:?}", Duration::from_millis(300)));
+    }
+    #[test]
+    fn test_bp_macro_call_scheduling_args() {
+        let mut macros = BPMacroTable::new();
+        macros.add_macro_line("EXPLOSION VIBRATE Duration:1 Motor:-1 Strength:$Strength");
+        macros.add_macro_line("EXPLOSION VIBRATE Duration:1 Motor:1 Strength:$Strength*0.5 Delay:0.5 Tag:boom");
+        macros.add_macro_line("WAVE VIBRATE Duration:1 Motor:-1 Strength:0.5 Delay:$Delay");
+        let bpcom = BPCommand::new("0 EXPLOSION Strength:0.8 Tag:attack Id:blast Priority:2 Delay:1 Repeat:2".to_string()).unwrap();
+        let bp_sim_events = bpcom.to_events(&macros, &BPGameClock::new());
+        assert_eq!(bp_sim_events.len(), 2);
+        for bpevent in bp_sim_events.iter() {
+            assert!(bpevent.tags.contains(&"attack".to_string()));
+            assert_eq!(bpevent.id, Some("blast".to_string()));
+            assert_eq!(bpevent.priority, 2);
+            assert_eq!(bpevent.repeats_remaining, 1);
+            assert_eq!(bpevent.repeat_interval, Duration::from_millis(1500));
+        }
+        assert_eq!(bp_sim_events[0].start_delay, Duration::from_secs(1));
+        assert_eq!(bp_sim_events[1].start_delay, Duration::from_millis(1500));
+        assert!(bp_sim_events[1].tags.contains(&"boom".to_string()));
+        //Arguments the macro passes on itself aren't applied twice
+        let bpcom = BPCommand::new("0 WAVE Delay:2".to_string()).unwrap();
+        assert_eq!(bpcom.to_events(&macros, &BPGameClock::new())[0].start_delay, Duration::from_secs(2));
+        //The call's tag is enough to cancel the whole macro
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(2);
+        bp_sim.add_event_queue(BPCommand::new("0 EXPLOSION Strength:0.8 Tag:attack".to_string()).unwrap().to_events(&macros, &BPGameClock::new()).into());
+        bp_sim.add_event(BPCommand::new("0 CANCEL Tag:attack".to_string()).unwrap().to_event().unwrap());
+        assert_eq!(bp_sim.pending_events.len(), 0);
+        assert!(bp_sim.events.iter().all(|bpevent| bpevent.finished));
+        assert!(BPCommand::new("0 EXPLOSION Strength:0.8 Delay:nan".to_string()).unwrap().to_events(&macros, &BPGameClock::new()).is_empty());
+    }
+    #[test]
+    fn test_bp_macro_self_reference_stops() {
+        let mut macros = BPMacroTable::new();
+        macros.add_macro_line("LOOP LOOP Strength:$Strength");
+        let bpcom = BPCommand::new("0 LOOP Strength:0.8".to_string()).unwrap();
+        assert_eq!(bpcom.to_events(&macros, &BPGameClock::new()).len(), 0);
+    }
+    #[test]
+    fn test_bp_macro_not_a_macro() {
+        let macros = BPMacroTable::new();
+        let bpcom = BPCommand::new("0 VIBRATE Duration:5 Motor:-1 Strength:0.2".to_string()).unwrap();
+        assert_eq!(bpcom.to_events(&macros, &BPGameClock::new()).len(), 1);
+    }
+    #[test]
+    fn test_bp_simulator_cancel_by_tag_and_id() {
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(1);
+        for line in ["0 VIBRATE Duration:5 Motor:0 Strength:0.2 Tag:attack,base",
+                     "0 VIBRATE Duration:5 Motor:0 Strength:0.1 Id:buzz",
+                     "0 VIBRATE Duration:5 Motor:0 Strength:0.3 Tag:attack Delay:1",
+                     "0 POWER Motor:0 Strength:0.05"] {
+            bp_sim.add_event(BPCommand::new(line.to_string()).unwrap().to_event().unwrap());
+        }
+        bp_sim.add_event(BPCommand::new("10 CANCEL Tag:attack".to_string()).unwrap().to_event().unwrap());
+        bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_millis(10));
+        assert_eq!(bp_sim.events.len(), 2);
+        assert_eq!(bp_sim.pending_events.len(), 0);
+        bp_sim.add_event(BPCommand::new("20 CANCEL Id:buzz".to_string()).unwrap().to_event().unwrap());
+        bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_millis(10));
+        assert_eq!(bp_sim.events.len(), 1);
+        assert!((bp_sim.mix_motor(0) - 0.05).abs() < 1e-9);
+    }
+    #[test]
+    fn test_bp_simulator_cancel_by_type() {
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(1);
+        bp_sim.add_event(BPCommand::new("0 POWER Motor:0 Strength:0.1".to_string()).unwrap().to_event().unwrap());
+        bp_sim.add_event(BPCommand::new("0 POWER Motor:0 Strength:0.2".to_string()).unwrap().to_event().unwrap());
+        bp_sim.add_event(BPCommand::new("0 VIBRATE Duration:5 Motor:0 Strength:0.3".to_string()).unwrap().to_event().unwrap());
+        bp_sim.add_event(BPCommand::new("10 CANCEL Type:power".to_string()).unwrap().to_event().unwrap());
+        bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_millis(10));
+        assert_eq!(bp_sim.events.len(), 1);
+        assert!((bp_sim.mix_motor(0) - 0.3).abs() < 1e-9);
+    }
+    #[test]
+    fn test_bp_simulator_pause_freezes_events() {
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(1);
+        bp_sim.add_event(BPCommand::new("0 VIBRATE Duration:0.5 Motor:0 Strength:0.5".to_string()).unwrap().to_event().unwrap());
+        bp_sim.add_event(BPCommand::new("0 PAUSE".to_string()).unwrap().to_event().unwrap());
+        bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_secs(5));
+        assert_eq!(bp_sim.events.len(), 1);
+        assert_eq!(format!("{:?}", bp_sim.get_vibrator_intensities()), format!("{:?}", vec![0.0]));
+        bp_sim.add_event(BPCommand::new("0 RESUME".to_string()).unwrap().to_event().unwrap());
+        bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_millis(100));
+        assert_eq!(bp_sim.events.len(), 1);
+        assert_eq!(format!("{:?}", bp_sim.get_vibrator_intensities()), format!("{:?}", vec![0.5]));
+    }
+    //BP Game Clock
+    #[test]
+    fn test_bp_mix_policies() {
+        let contributions = [(0.6, 0), (0.8, 0), (0.5, 1)];
+        assert!((BPMixPolicy::AdditiveSaturating.mix(&contributions, 0.5) - 1.0).abs() < 1e-9);
+        assert!((BPMixPolicy::Max.mix(&contributions, 0.5) - 0.8).abs() < 1e-9);
+        assert!((BPMixPolicy::RootSumSquare.mix(&[(0.3, 0), (0.4, 0)], 0.5) - 0.5).abs() < 1e-9);
+        assert!((BPMixPolicy::PriorityDucking.mix(&contributions, 0.5) - 1.0).abs() < 1e-9);
+        assert!((BPMixPolicy::PriorityDucking.mix(&[(0.2, 0), (0.4, 0), (0.3, 1)], 0.5) - 0.6).abs() < 1e-9);
+        assert_eq!(BPMixPolicy::Max.mix(&[], 0.5), 0.0);
+    }
+    #[test]
+    fn test_bp_simulator_mix_per_motor_and_tag() {
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(2);
+        bp_sim.add_event(BPCommand::new("0 MIX Policy:max Motor:1".to_string()).unwrap().to_event().unwrap());
+        bp_sim.add_event(BPCommand::new("0 POWER Motor:-1 Strength:0.7".to_string()).unwrap().to_event().unwrap());
+        bp_sim.add_event(BPCommand::new("0 VIBRATE Duration:5 Motor:-1 Strength:0.6 Tag:explosion".to_string()).unwrap().to_event().unwrap());
+        assert!((bp_sim.mix_motor(0) - 1.0).abs() < 1e-9);
+        assert!((bp_sim.mix_motor(1) - 0.7).abs() < 1e-9);
+        //Explosions only count as loud as the biggest one
+        bp_sim.add_event(BPCommand::new("0 MIX Policy:max Tag:explosion".to_string()).unwrap().to_event().unwrap());
+        bp_sim.add_event(BPCommand::new("0 VIBRATE Duration:5 Motor:0 Strength:0.2 Tag:explosion".to_string()).unwrap().to_event().unwrap());
+        bp_sim.set_motor_mix_policy(1, None);
+        bp_sim.mix_duck_factor = 0.5;
+        bp_sim.add_event(BPCommand::new("0 MIX Policy:duck".to_string()).unwrap().to_event().unwrap());
+        bp_sim.add_event(BPCommand::new("0 VIBRATE Duration:5 Motor:1 Strength:0.1 Priority:2".to_string()).unwrap().to_event().unwrap());
+        assert!((bp_sim.mix_motor(0) - 1.0).abs() < 1e-9);
+        assert!((bp_sim.mix_motor(1) - 0.75).abs() < 1e-9);
+        assert_eq!(bp_sim.events.len(), 4);
+    }
+    #[test]
+    fn test_bp_simulator_broadcast_reaches_later_motors() {
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_event(BPSimEvent::new_persistent(BPActionType::Power{ strength: 0.4, motor: -1 }));
+        bp_sim.add_multiple_vib_effectors(3);
+        bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_millis(10));
+        assert_eq!(format!("{:?}", bp_sim.get_vibrator_intensities()), format!("{:?}", vec![0.4, 0.4, 0.4]));
+        bp_sim.add_event(BPSimEvent::new_stop_event());
+        bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_millis(10));
+        assert_eq!(format!("{:?}", bp_sim.get_vibrator_intensities()), format!("{:?}", vec![0.0, 0.0, 0.0]));
+    }
+    proptest! {
+        #[test]
+        fn test_bp_simulator_floors_return_to_zero(
+            motor_count in 1usize..4,
+            policy_index in 0usize..4,
+            steps in proptest::collection::vec((0u8..3, 0.0f64..1.0, -1i8..4, 1u64..3000, 0u64..500), 1..40),
+        ) {
+            let mut bp_sim = BPSimulator::new();
+            bp_sim.add_multiple_vib_effectors(motor_count);
+            bp_sim.mix_policy = BPMixPolicy::ALL[policy_index];
+            for (kind, strength, motor, duration_millis, tick_millis) in steps {
+                let action = match kind {
+                    0 => BPActionType::Vibrate{ strength, motor },
+                    1 => BPActionType::Power{ strength, motor },
+                    _ => BPActionType::Set{ key: format!("key_{}", duration_millis % 3), strength, motor },
+                };
+                if kind == 0 {
+                    bp_sim.add_event(BPSimEvent::new(Duration::from_millis(duration_millis), action));
+                }
+                else {
+                    bp_sim.add_event(BPSimEvent::new_persistent(action));
+                }
+                bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_millis(tick_millis));
+            }
+            //Outlast every VIBRATE, then end the persistent events
+            bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_secs(5));
+            bp_sim.add_event(BPSimEvent::new(Duration::ZERO, BPActionType::Cancel{ filter: BPCancelFilter::Type("POWER".to_string()) }));
+            bp_sim.add_event(BPSimEvent::new(Duration::ZERO, BPActionType::Cancel{ filter: BPCancelFilter::Type("SET".to_string()) }));
+            bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_secs(5));
+            prop_assert_eq!(bp_sim.events.len(), 0);
+            for motor in 0..motor_count {
+                prop_assert_eq!(bp_sim.mix_motor(motor as i8), 0.0);
+            }
+            bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_secs(5));
+            prop_assert!(bp_sim.get_vibrator_intensities().iter().all(|intensity| *intensity == 0.0));
+        }
+    }
+    #[test]
+    fn test_bp_decay_models() {
+        let half_second = Duration::from_millis(500);
+        assert!((BPExponentialDecay{ half_life: half_second, linear_reduction: 0.0 }.decay(half_second, 0.8) - 0.4).abs() < 1e-9);
+        assert!((BPLinearDecay{ per_second: 1.0 }.decay(half_second, 0.8) - 0.3).abs() < 1e-9);
+        assert_eq!(BPLinearDecay{ per_second: 1.0 }.decay(half_second, 0.2), 0.0);
+        assert_eq!(BPNoDecay.decay(half_second, 0.8), 0.0);
+        let curve = BPCurveDecay::parse("0:1 1:1").unwrap();
+        assert!((curve.decay(half_second, 0.8) - 0.3).abs() < 1e-9);
+        assert!((BPCurveDecay::parse("0:0 1:2").unwrap().rate_at(0.25) - 0.5).abs() < 1e-9);
+        assert!(BPCurveDecay::parse("0:1 oops").is_none());
+        assert_eq!(new_decay_model("Curve").unwrap().name(), "Curve");
+    }
+    #[test]
+    fn test_bp_simulator_decay_per_effector() {
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(2);
+        bp_sim.set_decay_model(0, Box::new(BPNoDecay));
+        bp_sim.set_decay_model(1, Box::new(BPLinearDecay{ per_second: 1.0 }));
+        bp_sim.add_event(BPSimEvent::new(Duration::from_millis(50), BPActionType::Vibrate{ strength: 0.8, motor: -1 }));
+        bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_millis(100));
+        bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_millis(100));
+        //The event ends 50ms in, leaving 150ms of decay
+        let intensities = bp_sim.get_vibrator_intensities();
+        assert_eq!(intensities[0], 0.0);
+        assert!((intensities[1] - 0.65).abs() < 1e-9);
+        //Models carry over to the next device
+        bp_sim.reset_for_new_device();
+        bp_sim.add_multiple_vib_effectors(1);
+        assert_eq!(bp_sim.effectors[0].decay_model.name(), "None");
+    }
+    #[test]
+    fn test_bp_simulator_same_result_at_any_tick_rate() {
+        let run_at_tick_rate = |tick_millis: u64| {
+            let clock = BPManualClock::default();
+            let mut bp_sim = BPSimulator::with_clock(Box::new(clock.clone()));
+            bp_sim.add_multiple_vib_effectors(1);
+            bp_sim.add_event(BPSimEvent::new(Duration::from_millis(333), BPActionType::Vibrate{ strength: 0.9, motor: 0 }));
+            bp_sim.add_event(BPCommand::new("0 VIBRATE Duration:0.2 Motor:0 Strength:0.5 Delay:0.25".to_string()).unwrap().to_event().unwrap());
+            let mut intensities: Vec<f64> = Vec::new();
+            for _ in 0..(840 / tick_millis) {
+                clock.advance(Duration::from_millis(tick_millis));
+                bp_sim.tick();
+                intensities.push(bp_sim.get_vibrator_intensities()[0]);
+            }
+            (intensities.last().copied(), bp_sim.events.len(), bp_sim.pending_events.len())
+        };
+        let reference = run_at_tick_rate(840);
+        assert!(reference.0.unwrap() > 0.0);
+        for tick_millis in [7, 12, 60, 120] {
+            assert_eq!(format!("{:?}", run_at_tick_rate(tick_millis)), format!("{:?}", reference));
+        }
+    }
+    #[test]
+    fn test_bp_game_clock_estimates_speed() {
+        let mut game_clock = BPGameClock::new();
+        let start = Instant::now();
+        game_clock.observe_frame(300, start);
+        game_clock.observe_frame(330, start + Duration::from_millis(500));
+        assert_eq!(game_clock.game_speed(), 1.0);
+        game_clock.observe_frame(480, start + Duration::from_secs(3));
+        assert!((game_clock.game_speed() - 2.0).abs() < 1e-9);
+        assert_eq!(format!("{:?}", game_clock.frames_to_duration(60.0).unwrap()), format!("{:?}", Duration::from_secs(1)));
+    }
+    #[test]
+    fn test_bp_command_duration_frames_follow_game_speed() {
+        let mut game_clock = BPGameClock::new();
+        game_clock.set_speed_override(Some(3.0));
+        let bpcom = BPCommand::new("0 VIBRATE DurationFrames:90 Motor:-1 Strength:0.2 DelayFrames:45".to_string()).unwrap();
+        let bp_sim_event = bpcom.to_timed_event(&game_clock).unwrap();
+        assert_eq!(format!("{:?}", bp_sim_event.time_remaining), format!("{:?}", Duration::from_secs(1)));
+        assert_eq!(format!("{:?}", bp_sim_event.start_delay), format!("{:?}", Duration::from_millis(500)));
+    }
+    #[test]
+    fn test_bp_game_clock_clamps_speed_override() {
+        let mut game_clock = BPGameClock::new();
+        game_clock.set_speed_override(Some(1e-300));
+        assert_eq!(game_clock.game_speed(), GAME_SPEED_MIN);
+        game_clock.observe_latest_write(10000, Instant::now());
+        assert!(game_clock.frame_age(0, Instant::now()) >= Duration::from_secs(60));
+        game_clock.set_speed_override(Some(1e300));
+        assert_eq!(game_clock.game_speed(), GAME_SPEED_MAX);
+    }
+    #[test]
+    fn test_bp_sim_event_skip_time() {
+        let mut bp_sim_event = BPSimEvent::new(Duration::from_secs(2), BPActionType::Vibrate{ strength: 0.5, motor: -1 });
+        bp_sim_event.start_delay = Duration::from_secs(1);
+        assert!(bp_sim_event.skip_time(Duration::from_millis(1500)));
+        assert_eq!(format!("{:?}", bp_sim_event.start_delay), format!("{:?}", Duration::ZERO));
+        assert_eq!(format!("{:?}", bp_sim_event.time_remaining), format!("{:?}", Duration::from_millis(1500)));
+        assert!(!bp_sim_event.skip_time(Duration::from_secs(2)));
+    }
+    //BP Data Parser
+    #[test]
+    fn test_bp_data_parser_stale_commands() {
+        let cmdlog_file = TestTempFile::new("stale_cmdlog", "0 VIBRATE Duration:5 Motor:-1 Strength:0.1\n300 VIBRATE Duration:30 Motor:-1 Strength:0.2\n300 POWER Motor:-1 Strength:0.2\n900 VIBRATE Duration:1 Motor:-1 Strength:0.3\n");
+        let mut bp_parser = BPDataParser::new(cmdlog_file.path_string());
+        bp_parser.stale_policy = BPStaleCommandPolicy::Drop;
+        assert_eq!(bp_parser.get_new_events().len(), 2);
+        let mut bp_parser = BPDataParser::new(cmdlog_file.path_string());
+        bp_parser.stale_policy = BPStaleCommandPolicy::Compress;
+        let event_queue = bp_parser.get_new_events();
+        assert_eq!(event_queue.len(), 3);
+        assert!(event_queue[0].time_remaining <= Duration::from_secs(10));
+        assert!(event_queue[0].time_remaining > Duration::from_secs(9));
+        assert!(bp_parser.input_lag() < Duration::from_secs(5));
+    }
+    #[test]
+    fn test_bp_data_parser_catch_up_current_game() {
+        let cmdlog_file = TestTempFile::new("catch_up_cmdlog", "9000 POWER Motor:-1 Strength:0.9\n\
+            0 VIBRATE Duration:5 Motor:-1 Strength:0.1\n\
+            30 SET Key:eco_stall Strength:0.3\n\
+            60 POWER Motor:0 Strength:0.2\n\
+            90 SET Key:under_attack Strength:0.1\n\
+            300 CLEAR Key:under_attack\n\
+            870 VIBRATE Duration:2 Motor:-1 Strength:0.3\n\
+            900 VIBRATE Duration:0.5 Motor:-1 Strength:0.4\n");
+        let mut bp_parser = BPDataParser::new(cmdlog_file.path_string());
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(2);
+        bp_sim.add_event_queue(bp_parser.catch_up_current_game());
+        bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_millis(10));
+        //The POWER from the previous game and the finished VIBRATE are gone
+        assert_eq!(bp_sim.events.len(), 4);
+        assert!((bp_sim.mix_motor(0) - 1.0).abs() < 1e-9);
+        assert!((bp_sim.mix_motor(1) - 1.0).abs() < 1e-9);
+        assert_eq!(bp_parser.prev_reached_frame, 900);
+        assert_eq!(bp_parser.get_new_events().len(), 0);
+    }
+    #[test]
+    fn test_bp_data_parser_skips_catch_up_on_old_file() {
+        let cmdlog_file = TestTempFile::new("old_catch_up_cmdlog", "30 POWER Motor:-1 Strength:0.9\n60 SET Key:eco_stall Strength:0.3\n");
+        let mut bp_parser = BPDataParser::new(cmdlog_file.path_string());
+        bp_parser.max_catch_up_age = Duration::ZERO;
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(bp_parser.catch_up_current_game().len(), 0);
+        assert_eq!(bp_parser.prev_reached_frame, 60);
+        assert_eq!(bp_parser.get_new_events().len(), 0);
+    }
+    #[test]
+    fn test_bp_data_parser_session_lifecycle() {
+        let cmdlog_file = TestTempFile::new("session_cmdlog", "0 GAMESTART Map:Glacier\n30 POWER Motor:-1 Strength:0.2\n");
+        let mut bp_parser = BPDataParser::new(cmdlog_file.path_string());
+        let event_queue = bp_parser.get_new_events();
+        assert_eq!(format!("{:?}", event_queue[0].action), format!("{:?}", BPActionType::Stop));
+        assert_eq!(event_queue.len(), 2);
+        assert!(bp_parser.session().active);
+        assert_eq!(bp_parser.session().metadata.get("Map").unwrap(), "Glacier");
+        //Only new lines are read, even when the frame doesn't move
+        let mut cmdlog = std::fs::OpenOptions::new().append(true).open(&cmdlog_file.path).unwrap();
+        cmdlog.write_all(b"30 VIBRATE Duration:1 Motor:-1 Strength:0.2\n30 VIBRA").unwrap();
+        assert_eq!(bp_parser.get_new_events().len(), 1);
+        cmdlog.write_all(b"TE Duration:1 Motor:-1 Strength:0.2\n40 GAMEOVER\n").unwrap();
+        let event_queue = bp_parser.get_new_events();
+        assert_eq!(event_queue.len(), 2);
+        assert_eq!(format!("{:?}", event_queue[1].action), format!("{:?}", BPActionType::Stop));
+        assert!(!bp_parser.session().active);
+        //A new game that starts by overwriting the file
+        std::fs::write(&cmdlog_file.path, "0 VIBRATE Duration:1 Motor:-1 Strength:0.2\n").unwrap();
+        let event_queue = bp_parser.get_new_events();
+        assert_eq!(event_queue.len(), 2);
+        assert!(bp_parser.session().active);
+        assert_eq!(bp_parser.session().number, 2);
+    }
+    //BP Routing Filter
+    #[test]
+    fn test_bp_routing_filter() {
+        let everyone = BPCommand::new("0 VIBRATE Duration:1 Motor:-1 Strength:0.2".to_string()).unwrap();
+        let player_2 = BPCommand::new("0 VIBRATE Duration:1 Motor:-1 Strength:0.2 Player:2".to_string()).unwrap();
+        let player_3 = BPCommand::new("0 VIBRATE Duration:1 Motor:-1 Strength:0.2 Player:3".to_string()).unwrap();
+        let team_1 = BPCommand::new("0 VIBRATE Duration:1 Motor:-1 Strength:0.2 Team:1".to_string()).unwrap();
+        let mut routing = BPRoutingFilter::default();
+        assert!(routing.accepts(&everyone) && routing.accepts(&player_3) && routing.accepts(&team_1));
+        routing.player_id = "2".to_string();
+        routing.team_id = "1".to_string();
+        assert!(routing.accepts(&everyone));
+        assert!(routing.accepts(&player_2));
+        assert!(!routing.accepts(&player_3));
+        assert!(!routing.accepts(&team_1));
+        routing.receive_team_events = true;
+        assert!(routing.accepts(&team_1));
+    }
+    #[test]
+    fn test_bp_panic_command() {
+        let bp_sim_event = BPCommand::new("120 PANIC".to_string()).unwrap().to_event().unwrap();
+        assert_eq!(format!("{:?}", bp_sim_event.action), format!("{:?}", BPActionType::Panic));
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(1);
+        bp_sim.add_event(BPSimEvent::new_persistent(BPActionType::Power{ strength: 0.5, motor: -1 }));
+        bp_sim.add_event(bp_sim_event);
+        assert_eq!(bp_sim.events.len(), 0);
+    }
+    #[test]
+    fn test_bp_input_watchdog() {
+        let mut watchdog = BPInputWatchdog {
+            idle_timeout: Duration::from_secs(10),
+            fade_time: Duration::from_secs(2),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        assert_eq!(watchdog.update(Duration::from_secs(9), start), None);
+        assert_eq!(watchdog.update(Duration::from_secs(10), start), Some(BPWatchdogEvent::Tripped));
+        assert!((watchdog.output_scale(start + Duration::from_secs(1)) - 0.5).abs() < 1e-9);
+        assert_eq!(watchdog.update(Duration::from_secs(11), start + Duration::from_secs(1)), None);
+        assert_eq!(watchdog.update(Duration::from_secs(12), start + Duration::from_secs(2)), Some(BPWatchdogEvent::FadedOut));
+        assert_eq!(watchdog.update(Duration::from_secs(13), start + Duration::from_secs(3)), None);
+        assert_eq!(watchdog.output_scale(start + Duration::from_secs(3)), 0.0);
+        assert_eq!(watchdog.update(Duration::ZERO, start + Duration::from_secs(4)), Some(BPWatchdogEvent::Resumed));
+        assert_eq!(watchdog.output_scale(start + Duration::from_secs(4)), 1.0);
+    }
+    #[test]
+    fn test_bp_input_watchdog_held_while_paused() {
+        let mut watchdog = BPInputWatchdog {
+            idle_timeout: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(1);
+        bp_sim.add_event(BPSimEvent::new_persistent(BPActionType::Power{ strength: 0.5, motor: -1 }));
+        bp_sim.add_event(BPSimEvent::new(Duration::ZERO, BPActionType::Pause));
+        let start = Instant::now();
+        //A long pause with no input at all
+        for second in 0..100 {
+            assert!(bp_sim.is_paused());
+            watchdog.hold(start + Duration::from_secs(second));
+        }
+        bp_sim.add_event(BPSimEvent::new(Duration::ZERO, BPActionType::Resume));
+        assert_eq!(watchdog.update(Duration::from_secs(100), start + Duration::from_secs(100)), None);
+        assert_eq!(watchdog.update(Duration::from_secs(108), start + Duration::from_secs(108)), None);
+        assert_eq!(bp_sim.events.iter().filter(|bpevent| !bpevent.finished).count(), 1);
+        //Still trips once the game stays quiet after resuming
+        assert_eq!(watchdog.update(Duration::from_secs(109), start + Duration::from_secs(109)), Some(BPWatchdogEvent::Tripped));
+    }
+    #[test]
+    fn test_bp_input_watchdog_not_held_by_auto_pause_or_audible_pause() {
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(1);
+        bp_sim.add_event(BPSimEvent::new_persistent(BPActionType::Power{ strength: 0.5, motor: -1 }));
+        bp_sim.set_paused(true);
+        assert!(MyApp::input_watchdog_held(&bp_sim, false));
+        assert!(!MyApp::input_watchdog_held(&bp_sim, true));
+        bp_sim.silence_when_paused = false;
+        assert!(!MyApp::input_watchdog_held(&bp_sim, false));
+        //A stalled, auto-paused game that keeps vibrating and sends nothing
+        let mut watchdog = BPInputWatchdog {
+            idle_timeout: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let mut tripped = false;
+        for second in 0..=10 {
+            let now = start + Duration::from_secs(second);
+            if MyApp::input_watchdog_held(&bp_sim, true) {
+                watchdog.hold(now);
+            } else if watchdog.update(Duration::from_secs(second), now) == Some(BPWatchdogEvent::Tripped) {
+                tripped = true;
+            }
+        }
+        assert!(tripped);
+    }
+    #[test]
+    fn test_bp_data_parser_resumes_timed_out_session() {
+        let cmdlog_file = TestTempFile::new("watchdog_cmdlog", "30 VIBRATE Duration:1 Motor:-1 Strength:0.5\n");
+        let mut bp_parser = BPDataParser::new(cmdlog_file.path_string());
+        bp_parser.get_new_events();
+        let session_number = bp_parser.session().number;
+        bp_parser.end_session_for_inactivity();
+        assert!(!bp_parser.session().active);
+        assert!(bp_parser.session().timed_out);
+        let mut cmdlog = std::fs::OpenOptions::new().append(true).open(&cmdlog_file.path).unwrap();
+        cmdlog.write_all(b"60 VIBRATE Duration:1 Motor:-1 Strength:0.5\n").unwrap();
+        assert_eq!(bp_parser.get_new_events().len(), 1);
+        assert!(bp_parser.session().active);
+        assert_eq!(bp_parser.session().number, session_number);
+        assert!(bp_parser.time_since_input() < Duration::from_secs(1));
+    }
+    #[test]
+    fn test_bp_wellness_timer_breaks_and_session_end() {
+        let mut wellness_timer = BPWellnessTimer {
+            enabled: true,
+            max_active_time: Duration::from_secs(50),
+            break_every: Duration::from_secs(20),
+            break_length: Duration::from_secs(10),
+            session_length: Duration::from_secs(1000),
+            log_break_events: false,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        assert_eq!(wellness_timer.apply(vec![0.0], start), vec![0.0]);
+        //Idle time doesn't count towards the break
+        assert_eq!(wellness_timer.apply(vec![0.5], start + Duration::from_secs(100))[0], 0.5);
+        assert_eq!(wellness_timer.apply(vec![0.5], start + Duration::from_secs(119))[0], 0.5);
+        assert_eq!(wellness_timer.apply(vec![0.5], start + Duration::from_secs(120))[0], 0.0);
+        assert_eq!(wellness_timer.state(), BPWellnessState::Break { ends_at: start + Duration::from_secs(130) });
+        let event_queue: VecDeque<BPSimEvent> = VecDeque::from(vec![
+            BPSimEvent::new(Duration::from_secs(1), BPActionType::Vibrate{ strength: 0.5, motor: -1 }),
+            BPSimEvent::new_stop_event(),
+        ]);
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(1);
+        bp_sim.add_event(BPSimEvent::new_persistent(BPActionType::Set{ key: "eco_stall".to_string(), strength: 0.3, motor: -1 }));
+        let event_queue = wellness_timer.filter_events(event_queue, &bp_sim);
+        assert_eq!(event_queue.len(), 1);
+        assert_eq!(format!("{:?}", event_queue[0].action), format!("{:?}", BPActionType::Stop));
+        //Anything that lowers output or updates an existing key still gets through
+        let event_queue: VecDeque<BPSimEvent> = VecDeque::from(vec![
+            BPSimEvent::new_persistent(BPActionType::Power{ strength: 0.0, motor: -1 }),
+            BPSimEvent::new_persistent(BPActionType::Power{ strength: 0.5, motor: -1 }),
+            BPSimEvent::new_persistent(BPActionType::Set{ key: "eco_stall".to_string(), strength: 0.0, motor: -1 }),
+            BPSimEvent::new_persistent(BPActionType::Set{ key: "eco_stall".to_string(), strength: 0.6, motor: -1 }),
+            BPSimEvent::new_persistent(BPActionType::Set{ key: "under_attack".to_string(), strength: 0.2, motor: -1 }),
+            BPSimEvent::new_persistent(BPActionType::Set{ key: "under_attack".to_string(), strength: 0.0, motor: -1 }),
+        ]);
+        let event_queue = wellness_timer.filter_events(event_queue, &bp_sim);
+        assert_eq!(event_queue.iter().map(|bpevent| format!("{:?}", bpevent.action)).collect::<Vec<String>>(), vec![
+            format!("{:?}", BPActionType::Power{ strength: 0.0, motor: -1 }),
+            format!("{:?}", BPActionType::Set{ key: "eco_stall".to_string(), strength: 0.0, motor: -1 }),
+            format!("{:?}", BPActionType::Set{ key: "eco_stall".to_string(), strength: 0.6, motor: -1 }),
+            format!("{:?}", BPActionType::Set{ key: "under_attack".to_string(), strength: 0.0, motor: -1 }),
+        ]);
+        assert_eq!(wellness_timer.apply(vec![0.5], start + Duration::from_secs(130))[0], 0.5);
+        assert_eq!(wellness_timer.state(), BPWellnessState::Running);
+        //39 seconds of stimulation so far, the session cap is 50
+        assert_eq!(wellness_timer.apply(vec![0.5], start + Duration::from_secs(149))[0], 0.5);
+        assert_eq!(wellness_timer.apply(vec![0.5], start + Duration::from_secs(160))[0], 0.0);
+        assert_eq!(wellness_timer.state(), BPWellnessState::Ended);
+        wellness_timer.start_new_session();
+        assert_eq!(wellness_timer.apply(vec![0.5], start + Duration::from_secs(161))[0], 0.5);
+    }
+    #[test]
+    fn test_bp_dry_run_log() {
+        let log_file = TestTempFile::new("dry_run_log", "");
+        let mut dry_run_log = BPDryRunLog::create(&log_file.path_string()).unwrap();
+        dry_run_log.log_events(&VecDeque::from(vec![BPSimEvent::new_stop_event()]));
+        dry_run_log.log_output(&[0.25, 0.0]);
+        let logged = std::fs::read_to_string(&log_file.path).unwrap();
+        let lines: Vec<&str> = logged.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("EVENT") && lines[0].contains("Stop"));
+        assert!(lines[1].ends_with("OUTPUT [0.25, 0.0]"));
+    }
+    //BP Output Backend
+    #[test]
+    fn test_crash_handler_stops_in_process_device() {
+        let _lock = DEVICE_STOP_CLIENT_TEST_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let simulated_devices = BPSimulatedDevices::new(&["Massage Demo"]);
+        let mut client = BPIntifaceClient::with_connector(BPConnectorChoice::InProcess(simulated_devices.clone()));
+        client.connect().unwrap();
+        client.set_scalar(0, vec![1.0, 1.0]);
+        let report_file = TestTempFile::new("crash_report", "");
+        //Nothing drives the client's runtime from this thread, like a GUI thread stuck in a panic
+        handle_crash(&report_file.path, &"Test crash");
+        let report = std::fs::read_to_string(&report_file.path).unwrap();
+        assert!(report.contains("Test crash"));
+        let writes: Vec<Vec<u8>> = simulated_devices.writes().into_iter().map(|write| write.data).collect();
+        assert!(writes.ends_with(&[vec![0xF1, 0], vec![0xF2, 0]]) || writes.ends_with(&[vec![0xF2, 0], vec![0xF1, 0]]));
+        client.shutdown();
+    }
+    #[test]
+    fn test_bp_intiface_client_in_process() {
+        let _lock = DEVICE_STOP_CLIENT_TEST_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        //"Massage Demo" is what buttplug's device config calls a two vibrator Aneros test device
+        let simulated_devices = BPSimulatedDevices::new(&["Massage Demo"]);
+        let mut client = BPIntifaceClient::with_connector(BPConnectorChoice::InProcess(simulated_devices.clone()));
+        client.connect().unwrap();
+        assert!(client.is_connected());
+        let devices = client.devices();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].vibrator_count, 2);
+
+        client.set_scalar(0, vec![0.5, 1.0]);
+        let writes: Vec<Vec<u8>> = simulated_devices.writes().into_iter().map(|write| write.data).collect();
+        assert!(writes.contains(&vec![0xF1, 64]));
+        assert!(writes.contains(&vec![0xF2, 127]));
+
+        //Missing devices are reported, not written to
+        let write_count = simulated_devices.writes().len();
+        client.set_scalar(5, vec![1.0]);
+        assert_eq!(simulated_devices.writes().len(), write_count);
+
+        client.shutdown();
+        assert!(!client.is_connected());
+        let writes: Vec<Vec<u8>> = simulated_devices.writes().into_iter().map(|write| write.data).collect();
+        assert!(writes.ends_with(&[vec![0xF1, 0], vec![0xF2, 0]]) || writes.ends_with(&[vec![0xF2, 0], vec![0xF1, 0]]));
+    }
+
+    #[test]
+    fn test_bp_virtual_device_records_commands() {
+        let mut virtual_device = BPVirtualDevice::new(2);
+        assert_eq!(virtual_device.devices().len(), 0);
+        let output_backend: &mut dyn BPOutputBackend = &mut virtual_device;
+        output_backend.connect().unwrap();
+        assert_eq!(output_backend.devices()[0].vibrator_count, 2);
+        output_backend.set_scalar(0, vec![0.5]);
+        output_backend.linear(0, Duration::from_millis(300), 0.8);
+        output_backend.rotate(0, 0.4, false);
+        output_backend.set_scalar(3, vec![1.0, 1.0]);
+        output_backend.shutdown();
+        assert!(!output_backend.is_connected());
+        let commands: Vec<BPBackendCommand> = virtual_device.commands().iter().map(|(_, command)| command.clone()).collect();
+        assert_eq!(commands, vec![
+            BPBackendCommand::Scalar { device: 0, strengths: vec![0.5, 0.0] },
+            BPBackendCommand::Linear { device: 0, duration: Duration::from_millis(300), position: 0.8 },
+            BPBackendCommand::Rotate { device: 0, speed: 0.4, clockwise: false },
+            BPBackendCommand::Stop,
+        ]);
+        assert!(virtual_device.commands()[0].0 <= virtual_device.commands()[3].0);
+    }
+    //BP Output Quantizer
+    #[test]
+    fn test_bp_output_quantizer_rounds_to_steps() {
+        let quantized = BPOutputQuantizer::quantize(&[0.52, 0.52, 0.52], &[20, 0]);
+        assert_eq!(quantized, vec![9.5 / 20.0, 0.52, 0.52]);
+        assert_eq!(BPOutputQuantizer::quantize(&[0.99, 1.0, 0.001], &[127, 127, 127]), vec![124.5 / 127.0, 126.5 / 127.0, 0.0]);
+    }
+
+    #[test]
+    fn test_bp_output_quantizer_stays_under_safety_caps() {
+        let start = Instant::now();
+        for cap_percent in 0..=100 {
+            let mut limiter = BPSafetyLimiter {
+                global_max: cap_percent as f64 / 100.0,
+                ..Default::default()
+            };
+            let limited = limiter.apply(vec![1.0, 0.9, 0.5], start);
+            for steps in [3, 7, 20, 100, 127] {
+                let step_count = steps as f64;
+                let quantized = BPOutputQuantizer::quantize(&limited, &[steps, steps, steps]);
+                for motor in 0..quantized.len() {
+                    //The step buttplug ends up writing to the device
+                    let device_step = (quantized[motor] * step_count).ceil();
+                    assert!(device_step <= (limited[motor] * step_count).floor(), "{} steps turned {} into step {}", steps, limited[motor], device_step);
+                }
+            }
+        }
+        //Rounding to the nearest step would go over the cap, and so would sending 7/100 exactly
+        assert_eq!((BPOutputQuantizer::quantize(&[0.33], &[20])[0] * 20.0).ceil(), 6.0);
+        assert_eq!((BPOutputQuantizer::quantize(&[0.07], &[100])[0] * 100.0).ceil(), 7.0);
+    }
+
+    #[test]
+    fn test_bp_output_quantizer_skips_redundant_writes() {
+        let mut quantizer = BPOutputQuantizer {
+            keep_alive: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let steps = [20, 20];
+        assert_eq!(quantizer.prepare(0, &[0.5, 0.0], &steps, start), Some(vec![9.5 / 20.0, 0.0]));
+        //Too small a change for the device to feel
+        assert_eq!(quantizer.prepare(0, &[0.51, 0.01], &steps, start + Duration::from_millis(100)), None);
+        assert_eq!(quantizer.prepare(0, &[0.6, 0.0], &steps, start + Duration::from_millis(200)), Some(vec![11.5 / 20.0, 0.0]));
+        assert_eq!(quantizer.prepare(0, &[0.6, 0.0], &steps, start + Duration::from_millis(300)), None);
+        //Keep-alive resends the unchanged value
+        assert_eq!(quantizer.prepare(0, &[0.6, 0.0], &steps, start + Duration::from_millis(1200)), Some(vec![11.5 / 20.0, 0.0]));
+        assert_eq!((quantizer.writes_sent, quantizer.writes_skipped), (3, 2));
+
+        //Devices are tracked separately
+        assert_eq!(quantizer.prepare(1, &[0.6, 0.0], &steps, start + Duration::from_millis(1250)), Some(vec![11.5 / 20.0, 0.0]));
+        quantizer.reset();
+        assert_eq!(quantizer.prepare(0, &[0.6, 0.0], &steps, start + Duration::from_millis(1300)), Some(vec![11.5 / 20.0, 0.0]));
+        quantizer.enabled = false;
+        assert_eq!(quantizer.prepare(0, &[0.61, 0.0], &steps, start + Duration::from_millis(1400)), Some(vec![0.61, 0.0]));
+    }
+
+    //BP Output Scheduler
+    #[test]
+    fn test_bp_output_scheduler_adapts_to_latency() {
+        let mut scheduler = BPOutputScheduler::default();
+        let start = Instant::now();
+        assert!(scheduler.is_due(0, start));
+        //Fast device stays at the minimum period
+        scheduler.record(0, start, Some(Duration::from_millis(10)));
+        assert_eq!(scheduler.period(0), Duration::from_millis(100));
+        assert!(!scheduler.is_due(0, start + Duration::from_millis(50)));
+        assert!(scheduler.is_due(0, start + Duration::from_millis(100)));
+
+        //Slow device gets twice its latency
+        scheduler.record(1, start, Some(Duration::from_millis(300)));
+        assert_eq!(scheduler.period(1), Duration::from_millis(600));
+        assert!(!scheduler.is_due(1, start + Duration::from_millis(300)));
+        //Latency is smoothed, one fast write doesn't undo it
+        scheduler.record(1, start + Duration::from_millis(600), Some(Duration::from_millis(100)));
+        assert_eq!(scheduler.period(1), Duration::from_millis(480));
+        scheduler.adaptive = false;
+        assert_eq!(scheduler.period(1), Duration::from_millis(100));
+        scheduler.adaptive = true;
+
+        //Really slow devices are capped at the max period
+        scheduler.record(2, start, Some(Duration::from_secs(5)));
+        assert_eq!(scheduler.period(2), Duration::from_millis(1000));
+
+        //Skipped writes move the schedule but don't count toward the rate
+        scheduler.record(0, start + Duration::from_millis(100), None);
+        scheduler.record(0, start + Duration::from_millis(200), Some(Duration::from_millis(10)));
+        assert_eq!(scheduler.achieved_rate(0, start + Duration::from_millis(200)), 2.0);
+        assert_eq!(scheduler.achieved_rate(0, start + Duration::from_millis(1100)), 1.0);
+
+        scheduler.reset();
+        assert_eq!(scheduler.achieved_rate(0, start), 0.0);
+        assert!(scheduler.is_due(1, start));
+    }
+
+    #[test]
+    fn test_bp_simulator_caps_catch_up_after_long_gap() {
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(1);
+        bp_sim.add_event(BPSimEvent::new(Duration::from_secs(20), BPActionType::Vibrate{ strength: 0.5, motor: -1 }));
+        bp_sim.process_tick(bp_sim.last_sim_instant + Duration::from_secs(3600));
+        assert_eq!(bp_sim.events.len(), 1);
+        assert_eq!(bp_sim.events[0].time_remaining, Duration::from_secs(20) - SIM_MAX_CATCH_UP);
+        assert_eq!(bp_sim.unstepped_time, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_split_motors_by_device() {
+        let device = |index: usize, vibrator_count: usize| BPDeviceInfo {
+            index,
+            name: "Test".to_string(),
+            vibrator_count,
+            vibrator_steps: vec![20; vibrator_count],
+            linear_count: 0,
+            rotator_count: 0,
+        };
+        let device_layout = vec![device(3, 2), device(5, 0), device(8, 1)];
+        let split = |intensities: &[f64]| -> Vec<(usize, Vec<f64>)> {
+            split_motors_by_device(intensities, &device_layout).into_iter().map(|(device, motors)| (device.index, motors)).collect()
+        };
+        //Devices without vibrators get nothing
+        assert_eq!(split(&[0.1, 0.2, 0.3]), vec![(3, vec![0.1, 0.2]), (8, vec![0.3])]);
+        //Missing motors are left for the backend to fill in
+        assert_eq!(split(&[0.1]), vec![(3, vec![0.1]), (8, vec![])]);
+    }
+
+    //BP Safety Limiter
+    #[test]
+    fn test_bp_safety_limiter_caps() {
+        let mut limiter = BPSafetyLimiter {
+            global_max: 0.8,
+            ..Default::default()
+        };
+        limiter.motor_max.insert(1, 0.3);
+        let limited = limiter.apply(vec![1.5, 0.5, -0.2], Instant::now());
+        assert_eq!(format!("{:?}", limited), format!("{:?}", vec![0.8, 0.3, 0.0]));
+    }
+    #[test]
+    fn test_bp_safety_limiter_on_time() {
+        let mut limiter = BPSafetyLimiter {
+            limit_on_time: true,
+            max_on_time: Duration::from_secs(10),
+            rest_time: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        assert_eq!(limiter.apply(vec![0.5], start)[0], 0.5);
+        assert_eq!(limiter.apply(vec![0.5], start + Duration::from_secs(9))[0], 0.5);
+        assert_eq!(limiter.apply(vec![0.5], start + Duration::from_secs(10))[0], 0.0);
+        assert_eq!(limiter.apply(vec![0.5], start + Duration::from_secs(14))[0], 0.0);
+        assert_eq!(limiter.apply(vec![0.5], start + Duration::from_secs(15))[0], 0.5);
+    }
+    #[test]
+    fn test_bp_safety_limiter_energy_budget() {
+        let mut limiter = BPSafetyLimiter {
+            limit_energy: true,
+            energy_window: Duration::from_secs(60),
+            energy_budget: 10.0,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        limiter.apply(vec![1.0, 1.0], start);
+        //20 full strength seconds used against a budget of 10
+        let limited = limiter.apply(vec![1.0, 1.0], start + Duration::from_secs(10));
+        assert!((limited[0] - 0.5).abs() < 1e-9);
+        //Once the window has moved past the burst output goes back to normal
+        limiter.apply(vec![0.0, 0.0], start + Duration::from_secs(11));
+        let limited = limiter.apply(vec![1.0, 1.0], start + Duration::from_secs(80));
+        assert_eq!(limited[0], 1.0);
+    }
+    //BP Command Source
+    #[test]
+    fn test_bp_command_source_weighting_and_shared_reset() {
+        let cmdlog_file = TestTempFile::new("source_cmdlog", "0 VIBRATE Duration:1 Motor:-1 Strength:0.5\n0 RESET\n");
+        let mut source = BPCommandSource::new(cmdlog_file.path_string());
+        source.weight = 0.5;
+        let event_queue = source.get_new_events(true);
+        assert_eq!(event_queue.len(), 2);
+        assert_eq!(format!("{:?}", event_queue[0].action), format!("{:?}", BPActionType::Vibrate{ strength: 0.25, motor: -1 }));
+        assert!(event_queue[0].tags.contains(&source.tag));
+        assert_eq!(format!("{:?}", event_queue[1].action), format!("{:?}", BPActionType::Cancel{ filter: BPCancelFilter::Tag(source.tag.clone()) }));
+        source.enabled = false;
+        let mut cmdlog = std::fs::OpenOptions::new().append(true).open(&cmdlog_file.path).unwrap();
+        cmdlog.write_all(b"0 VIBRATE Duration:1 Motor:-1 Strength:0.5\n0 PANIC\n").unwrap();
+        //Only the panic gets past a disabled source
+        let event_queue = source.get_new_events(true);
+        assert_eq!(event_queue.len(), 1);
+        assert_eq!(format!("{:?}", event_queue[0].action), format!("{:?}", BPActionType::Panic));
+    }
+    #[test]
+    fn test_bp_command_source_scopes_shared_commands() {
+        let first_file = TestTempFile::new("first_source_cmdlog", "0 POWER Motor:0 Strength:0.5\n0 SET Key:eco_stall Strength:0.2\n");
+        let second_file = TestTempFile::new("second_source_cmdlog", "0 POWER Motor:0 Strength:0.3\n0 SET Key:eco_stall Strength:0.4\n");
+        let mut first = BPCommandSource::new(first_file.path_string());
+        let mut second = BPCommandSource::new(second_file.path_string());
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(1);
+        bp_sim.add_event_queue(first.get_new_events(true));
+        bp_sim.add_event_queue(second.get_new_events(true));
+        let running = |bp_sim: &BPSimulator, tag: &String| bp_sim.events.iter().filter(|bpevent| !bpevent.finished && bpevent.tags.contains(tag)).count();
+        //Same key from two sources makes two keyed events
+        assert_eq!(running(&bp_sim, &first.tag), 2);
+        assert_eq!(running(&bp_sim, &second.tag), 2);
+        let mut cmdlog = std::fs::OpenOptions::new().append(true).open(&first_file.path).unwrap();
+        cmdlog.write_all(b"10 POWER Motor:0 Strength:0\n10 CLEAR Key:eco_stall\n").unwrap();
+        bp_sim.add_event_queue(first.get_new_events(true));
+        assert_eq!(running(&bp_sim, &first.tag), 0);
+        assert_eq!(running(&bp_sim, &second.tag), 2);
+        let mut cmdlog = std::fs::OpenOptions::new().append(true).open(&first_file.path).unwrap();
+        cmdlog.write_all(b"20 POWER Motor:0 Strength:0.5\n30 CANCEL Type:POWER\n").unwrap();
+        bp_sim.add_event_queue(first.get_new_events(true));
+        assert_eq!(running(&bp_sim, &first.tag), 0);
+        assert_eq!(running(&bp_sim, &second.tag), 2);
+    }
+    #[test]
+    fn test_bp_command_source_cancel_event() {
+        let first = BPCommandSource::new("first_cmdlog.txt".to_string());
+        let second = BPCommandSource::new("second_cmdlog.txt".to_string());
+        let mut bp_sim = BPSimulator::new();
+        bp_sim.add_multiple_vib_effectors(1);
+        let mut first_power = BPSimEvent::new_persistent(BPActionType::Power{ strength: 0.5, motor: -1 });
+        first_power.tags.push(first.tag.clone());
+        let mut second_power = BPSimEvent::new_persistent(BPActionType::Power{ strength: 0.2, motor: -1 });
+        second_power.tags.push(second.tag.clone());
+        bp_sim.add_event(first_power);
+        bp_sim.add_event(second_power);
+        bp_sim.add_event(first.cancel_event());
+        let running: Vec<&BPSimEvent> = bp_sim.events.iter().filter(|bpevent| !bpevent.finished).collect();
+        assert_eq!(running.len(), 1);
+        assert!(running[0].tags.contains(&second.tag));
+    }
 }
\ No newline at end of file